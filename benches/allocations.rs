@@ -0,0 +1,50 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust_matchspec::matchspec::MatchSpec;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Wraps the system allocator so we can count how many allocations a parse makes
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const SPEC: &str = "conda-forge/linux-64::numpy>=1.20,<2.0[build=py39*, build_number='>=2']";
+
+/// Returns the number of allocations made while running f
+fn count_allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(f());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn allocations_benchmark(c: &mut Criterion) {
+    println!(
+        "Allocations per parse: owned {}, borrowed {}",
+        count_allocations(|| SPEC.parse::<MatchSpec>()),
+        count_allocations(|| MatchSpec::parse_borrowed(SPEC)),
+    );
+
+    c.bench_function("Owned parse", |b| {
+        b.iter(|| black_box(SPEC).parse::<MatchSpec>())
+    });
+    c.bench_function("Borrowed parse", |b| {
+        b.iter(|| MatchSpec::parse_borrowed(black_box(SPEC)))
+    });
+}
+
+criterion_group!(benches, allocations_benchmark);
+criterion_main!(benches);
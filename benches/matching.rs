@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust_matchspec::matchspec::MatchSpec;
+use rust_matchspec::package_candidate::PackageCandidate;
+
+fn load_candidates() -> Vec<PackageCandidate> {
+    let repodata_file = format!(
+        "{}/test_data/repodata-linux-64.json",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let repodata = std::fs::read_to_string(repodata_file).expect("reading repodata");
+    PackageCandidate::from_repodata_json(&repodata).expect("parsing repodata")
+}
+
+/// Benchmarks matching a spec against every candidate in the repodata, for a few common kinds of
+/// spec. Parsing happens outside of the timed loop so only the matching path is measured.
+fn matching_benchmark(c: &mut Criterion) {
+    let candidates = load_candidates();
+    let specs = [
+        ("Match package name only", "openssl"),
+        ("Match version range", "numpy>=1.20,<2.0"),
+        ("Match version glob", "python=3.9.*"),
+        ("Match build glob", "numpy[build=py39*]"),
+        ("Match package glob", "py*>=1.0"),
+        // None of the linux-64 candidates can match, which cheap checks should find quickly
+        ("Match subdir mismatch", "py*>=1.0[subdir=osx-64]"),
+        ("Match all possible matchers", "main/linux-64::tensorflow>=1.9.2[license=\"Apache 2.0\", build_number='>=1']"),
+    ];
+
+    for (name, spec) in specs {
+        let ms: MatchSpec = spec.parse().unwrap();
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                candidates
+                    .iter()
+                    .filter(|pc| black_box(&ms).is_match(*pc))
+                    .count()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, matching_benchmark);
+criterion_main!(benches);
@@ -1,7 +1,28 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use rust_matchspec::matchspec::MatchSpec;
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::time::{Duration, Instant};
+
+/// How many of the slowest specs to report and benchmark
+const SLOWEST_COUNT: usize = 10;
+
+/// How many times each spec is parsed when looking for the slowest specs
+const SLOWEST_ROUNDS: usize = 20;
+
+fn load_depends() -> Vec<String> {
+    let depends_file = format!(
+        "{}/test_data/linux_64-depends.txt",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let repodata_depends_buffer =
+        BufReader::new(File::open(depends_file).expect("opening repodata depends file"));
+    repodata_depends_buffer
+        .lines()
+        .map(|l| l.unwrap())
+        .collect()
+}
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("Package name only", |b| {
@@ -25,23 +46,60 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 
     c.bench_function("Repodata depends", |b| {
-        let depends_file = format!(
-            "{}/test_data/linux_64-depends.txt",
-            env!("CARGO_MANIFEST_DIR")
-        );
-        let repodata_depends_buffer =
-            BufReader::new(File::open(depends_file).expect("opening repodata depends file"));
-        let depends: Vec<String> = repodata_depends_buffer
-            .lines()
-            .map(|l| l.unwrap())
-            .collect();
+        let depends = load_depends();
         b.iter(|| {
             for d in &depends {
                 d.parse::<MatchSpec>().unwrap();
             }
         })
     });
+
+    c.bench_function("Repodata depends validation only", |b| {
+        let depends = load_depends();
+        b.iter(|| {
+            for d in &depends {
+                assert!(MatchSpec::is_valid(black_box(d)));
+            }
+        })
+    });
+}
+
+/// Times every unique spec in the repodata depends individually, reports the slowest ones, and
+/// then benchmarks just those. This is meant to guide parser optimization, the timings of the
+/// individual specs are the fastest of several rounds to reduce noise.
+fn slowest_specs_benchmark(c: &mut Criterion) {
+    let depends: BTreeSet<String> = load_depends().into_iter().collect();
+
+    let mut timings: Vec<(Duration, &String)> = depends
+        .iter()
+        .map(|d| {
+            let fastest = (0..SLOWEST_ROUNDS)
+                .map(|_| {
+                    let start = Instant::now();
+                    let _ = black_box(d.as_str()).parse::<MatchSpec>();
+                    start.elapsed()
+                })
+                .min()
+                .unwrap_or_default();
+            (fastest, d)
+        })
+        .collect();
+    timings.sort_by_key(|t| std::cmp::Reverse(t.0));
+    timings.truncate(SLOWEST_COUNT);
+
+    println!("Slowest {} of {} unique repodata depends:", SLOWEST_COUNT, depends.len());
+    for (duration, d) in &timings {
+        println!("{:>10?}  {}", duration, d);
+    }
+
+    c.bench_function("Slowest repodata depends", |b| {
+        b.iter(|| {
+            for (_, d) in &timings {
+                black_box(d.as_str()).parse::<MatchSpec>().unwrap();
+            }
+        })
+    });
 }
 
-criterion_group!(benches, criterion_benchmark);
+criterion_group!(benches, criterion_benchmark, slowest_specs_benchmark);
 criterion_main!(benches);
@@ -20,6 +20,7 @@ const IC_CL: u16 = 128;  // Colon; :
 const IC_BA: u16 = 256;  // Bar; |
 const IC_LB: u16 = 512;  // Left bracket; [
 const IC_RB: u16 = 1024; // Right bracket; ]
+const IC_PL: u16 = 2048; // Plus; +
 
 const INPUT_CLASS_BITMASK: u8 = 0x7F; // Mask out the high bit, since
                                       // our table only has 128 entries.
@@ -30,7 +31,7 @@ static INPUT_CLASS_TABLE:&'static [u16] = &[
     IC_NM, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM,
     IC_NM, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM,
     IC_NM, IC_NM, IC_QU, IC_NM, IC_NM, IC_NM, IC_NM, IC_QU,
-    IC_NM, IC_NM, IC_GL, IC_NM, IC_CO, IC_DU, IC_PE, IC_FS,
+    IC_NM, IC_NM, IC_GL, IC_PL, IC_CO, IC_DU, IC_PE, IC_FS,
     IC_AN, IC_AN, IC_AN, IC_AN, IC_AN, IC_AN, IC_AN, IC_AN,
     IC_AN, IC_AN, IC_CL, IC_NM, IC_NM, IC_NM, IC_NM, IC_NM,
     IC_NM, IC_AN, IC_AN, IC_AN, IC_AN, IC_AN, IC_AN, IC_AN,
@@ -64,6 +65,10 @@ pub fn is_any_valid_str_with_glob(c: char) -> bool {
     filter_char(c, IC_AN | IC_DU | IC_PE | IC_GL)
 }
 
+pub fn is_version_str_with_glob(c: char) -> bool {
+    filter_char(c, IC_AN | IC_DU | IC_PE | IC_GL | IC_PL)
+}
+
 pub fn is_quote(c: char) -> bool {
     filter_char(c, IC_QU)
 }
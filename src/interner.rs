@@ -0,0 +1,133 @@
+use crate::error::MatchSpecError;
+use crate::matchspec::*;
+use crate::matchspec_ref::MatchSpecRef;
+use crate::package_candidate::PackageCandidate;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Shares the storage of identical package names. When loading hundreds of thousands of specs
+/// most of them name the same few thousand packages, so interning the names saves a `String`
+/// allocation per spec.
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner::default()
+    }
+
+    /// Returns the shared copy of `name`, adding it to the interner if it hasn't been seen yet
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        match self.names.get(name) {
+            Some(interned) => interned.clone(),
+            None => {
+                let interned: Arc<str> = Arc::from(name);
+                self.names.insert(interned.clone());
+                interned
+            }
+        }
+    }
+
+    /// The number of distinct names in the interner
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// A MatchSpec whose package name is shared with every other InternedMatchSpec created from the
+/// same Interner. Created with `MatchSpec::parse_interned`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternedMatchSpec {
+    pub package: Arc<str>,
+    // The package of this MatchSpec is left empty, `package` is used instead
+    matchspec: MatchSpec,
+}
+
+impl InternedMatchSpec {
+    pub fn is_match(&self, pc: &PackageCandidate) -> bool {
+        self.matchspec.is_name_match_against(&self.package, &pc.name, false)
+            && self.matchspec.is_match_except_name(pc, &MatchOptions::default())
+    }
+
+    /// Copies the name back in to create a regular MatchSpec
+    pub fn to_matchspec(&self) -> MatchSpec {
+        MatchSpec {
+            package: self.package.to_string(),
+            ..self.matchspec.clone()
+        }
+    }
+}
+
+impl From<InternedMatchSpec> for MatchSpec {
+    fn from(interned: InternedMatchSpec) -> Self {
+        MatchSpec {
+            package: interned.package.to_string(),
+            ..interned.matchspec
+        }
+    }
+}
+
+impl MatchSpec {
+    /// Parses a MatchSpec, sharing the package name with any other spec parsed by the same
+    /// Interner.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    /// use rust_matchspec::interner::Interner;
+    ///
+    /// let mut interner = Interner::new();
+    /// let a = MatchSpec::parse_interned("numpy>=1.20", &mut interner).unwrap();
+    /// let b = MatchSpec::parse_interned("numpy<2.0", &mut interner).unwrap();
+    /// assert_eq!(interner.len(), 1);
+    /// assert_eq!(&*a.package, "numpy");
+    /// ```
+    pub fn parse_interned(s: &str, interner: &mut Interner) -> Result<InternedMatchSpec, MatchSpecError> {
+        let ms = MatchSpec::parse_borrowed(s)?;
+        let package = interner.intern(ms.package);
+        Ok(InternedMatchSpec {
+            package,
+            matchspec: MatchSpecRef { package: "", ..ms }.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod interner {
+        use crate::interner::*;
+        use crate::package_candidate::test_candidate;
+
+        #[test]
+        fn shared_names() {
+            let mut interner = Interner::new();
+            let a = MatchSpec::parse_interned("numpy>=1.20", &mut interner).unwrap();
+            let b = MatchSpec::parse_interned("conda-forge::numpy 1.21.0 py39h_0", &mut interner).unwrap();
+            let c = MatchSpec::parse_interned("scipy", &mut interner).unwrap();
+
+            assert!(Arc::ptr_eq(&a.package, &b.package));
+            assert!(!Arc::ptr_eq(&a.package, &c.package));
+            assert_eq!(interner.len(), 2);
+
+            // Converting back gives the same thing as a regular parse
+            assert_eq!(b.to_matchspec(), "conda-forge::numpy 1.21.0 py39h_0".parse::<MatchSpec>().unwrap());
+            assert_eq!(MatchSpec::from(c), "scipy".parse::<MatchSpec>().unwrap());
+        }
+
+        #[test]
+        fn interned_matching() {
+            let mut interner = Interner::new();
+            let ms = MatchSpec::parse_interned("tensorflow*>=2.0[exclude=tensorflow-gpu]", &mut interner).unwrap();
+
+            assert!(ms.is_match(&test_candidate("tensorflow-cpu", "2.9.1")));
+            assert!(!ms.is_match(&test_candidate("tensorflow-gpu", "2.9.1")));
+            assert!(!ms.is_match(&test_candidate("tensorflow", "1.15.0")));
+            assert!(!ms.is_match(&test_candidate("pytorch", "2.0.0")));
+        }
+    }
+}
@@ -2,9 +2,18 @@
 
 pub mod error;
 mod input_table;
+pub mod interner;
 pub mod matchspec;
+pub mod matchspec_group;
+pub mod matchspec_ref;
 pub mod package_candidate;
 mod parsers;
 pub mod python;
+pub mod repodata;
+#[cfg(feature = "shell-glob")]
+mod shell_glob;
+mod timestamp;
+mod version_order;
+mod version_range;
 
 pub use crate::matchspec::*;
@@ -1,26 +1,64 @@
 use crate::error::MatchSpecError;
 use crate::input_table::*;
+use crate::matchspec_ref::MatchSpecRef;
 use crate::package_candidate::*;
 use crate::parsers::*;
+use crate::timestamp::*;
+use crate::version_range::*;
 use nom::branch::alt;
+use nom::combinator::{eof, map};
 use nom::error::Error as NomError;
+use nom::sequence::terminated;
 use nom::Finish;
 use pyo3::prelude::*;
-use std::fmt::Debug;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Debug, Display, Formatter};
 use std::str::FromStr;
-use version_compare::{compare_to, Cmp};
+
+/// True if the string has a glob in it. With the `shell-glob` feature that includes `?` and
+/// `[a-z]` character classes. The parser only lets those through in quoted values like
+/// `[build='py3[0-9]*']`, so for package names they only work on a glob set directly.
+pub(crate) fn has_glob(s: &str) -> bool {
+    #[cfg(feature = "shell-glob")]
+    if crate::shell_glob::has_shell_glob(s) {
+        return true;
+    }
+    s.contains('*')
+}
 
 /// Matches a string with a string (possibly) containing globs
-fn is_match_glob_str(glob_str: &str, match_str: &str) -> bool {
-    let mut index: Option<usize> = Some(0);
-    let mut it = glob_str.split('*').peekable();
-    while let Some(part) = it.next() {
-        index = match_str.get(index.unwrap()..).and_then(|s| s.find(part));
-        if index.is_none() || (it.peek().is_none() && !match_str.ends_with(part)) {
-            return false;
+pub(crate) fn is_match_glob_str(glob_str: &str, match_str: &str) -> bool {
+    #[cfg(feature = "shell-glob")]
+    if crate::shell_glob::has_shell_glob(glob_str) {
+        return crate::shell_glob::is_match_shell_glob(glob_str, match_str);
+    }
+
+    let mut parts = glob_str.split('*');
+
+    // Everything before the first glob has to be at the start
+    let Some(mut rest) = match_str.strip_prefix(parts.next().unwrap_or_default()) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // No globs at all, so it has to be an exact match
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            // Each part between globs has to appear in order without overlapping
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            // Everything after the last glob has to be at the end
+            rest.ends_with(last)
         }
     }
-    true
 }
 
 /// Enum that is used for representating the selector types.
@@ -32,6 +70,9 @@ pub enum Selector {
     LessThanOrEqualTo,
     NotEqualTo,
     EqualTo,
+    ExactlyEqualTo,
+    /// `~=`, see `compatible_upper_bound`
+    CompatibleRelease,
 }
 
 impl<S> From<S> for Selector
@@ -45,40 +86,185 @@ impl<S> From<S> for Selector
             "<" => Self::LessThan,
             "<=" => Self::LessThanOrEqualTo,
             "!=" => Self::NotEqualTo,
+            "===" => Self::ExactlyEqualTo,
+            "~=" => Self::CompatibleRelease,
             _ => Self::EqualTo,
         }
     }
 }
 
+/// Strict parsing of a selector. Unlike the `From` implementation this errors on anything that
+/// isn't a known operator instead of falling back to `Selector::EqualTo`.
+/// ```
+/// use rust_matchspec::Selector;
+///
+/// assert_eq!(">=".parse(), Ok(Selector::GreaterThanOrEqualTo));
+/// assert!("=>".parse::<Selector>().is_err());
+/// ```
+impl FromStr for Selector {
+    type Err = MatchSpecError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match terminated(selector_parser, eof)(s).finish() {
+            Ok((_, selector)) => Ok(selector.into()),
+            Err(NomError { input: _, code: _ }) => Err(MatchSpecError {
+                message: format!("Invalid selector '{}'", s),
+            }),
+        }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let selector = match self {
+            Selector::GreaterThan => ">",
+            Selector::GreaterThanOrEqualTo => ">=",
+            Selector::LessThan => "<",
+            Selector::LessThanOrEqualTo => "<=",
+            Selector::NotEqualTo => "!=",
+            Selector::EqualTo => "==",
+            Selector::ExactlyEqualTo => "===",
+            Selector::CompatibleRelease => "~=",
+        };
+        write!(f, "{}", selector)
+    }
+}
+
+/// Splits `+build` metadata off of a version
+fn split_build_metadata(version: &str) -> (&str, Option<&str>) {
+    match version.split_once('+') {
+        Some((version, metadata)) => (version, Some(metadata)),
+        None => (version, None),
+    }
+}
+
+/// True if the version looks like a pre-release, meaning one of its segments has a `dev`, `rc`,
+/// `alpha`, `beta` or `pre` tag, or a single letter tag followed by a number like `1.0a1`.
+fn is_prerelease(version: &str) -> bool {
+    strip_build_metadata(version)
+        .split(['.', '-', '_'])
+        .any(|segment| {
+            let tag = segment.trim_start_matches(|c: char| c.is_ascii_digit());
+            let number = tag.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+            match tag[..tag.len() - number.len()].to_lowercase().as_str() {
+                "dev" | "rc" | "alpha" | "beta" | "pre" | "preview" => true,
+                "a" | "b" | "c" => !number.is_empty(),
+                _ => false,
+            }
+        })
+}
+
+/// Removes `+build` metadata from a version, it is ignored by every comparison except `===`
+pub(crate) fn strip_build_metadata(version: &str) -> &str {
+    split_build_metadata(version).0
+}
+
+/// The exclusive upper bound of a `~=` compatible release, found by dropping the last segment of
+/// the version and incrementing the one before it. So `~=1.2.3` means `>=1.2.3,<1.3` and `~=1.2`
+/// means `>=1.2,<2`. None for a version with a single segment, like `~=1`, which isn't allowed.
+/// ```
+/// use rust_matchspec::matchspec::compatible_upper_bound;
+///
+/// assert_eq!(compatible_upper_bound("1.2.3"), Some("1.3".to_string()));
+/// assert_eq!(compatible_upper_bound("1!2.0"), Some("1!3".to_string()));
+/// assert_eq!(compatible_upper_bound("1"), None);
+/// ```
+pub fn compatible_upper_bound(version: &str) -> Option<String> {
+    let version = strip_build_metadata(version);
+    let (prefix, _) = version.rsplit_once('.')?;
+    prefix_upper_bound(prefix)
+}
+
+/// The first version after every version starting with `prefix`, found by incrementing the last
+/// segment. So the `1.11.*` series ends before `1.12`. None if the last segment doesn't start
+/// with a number.
+pub(crate) fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let (epoch, prefix) = match prefix.split_once('!') {
+        Some((epoch, prefix)) => (format!("{}!", epoch), prefix),
+        None => (String::new(), prefix),
+    };
+
+    let (kept, last) = match prefix.rsplit_once('.') {
+        Some((kept, last)) => (format!("{}.", kept), last),
+        None => (String::new(), prefix),
+    };
+    let digits = last.len() - last.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let incremented = last[..digits].parse::<u64>().ok()? + 1;
+    Some(format!("{}{}{}", epoch, kept, incremented))
+}
+
 impl Selector {
     pub fn boolean_operator(&self) -> fn(&str, &str) -> bool {
         match self {
             Selector::EqualTo => Selector::eq,
+            Selector::ExactlyEqualTo => Selector::exactly_eq,
             Selector::NotEqualTo => Selector::ne,
             Selector::LessThan => Selector::lt,
             Selector::LessThanOrEqualTo => Selector::le,
             Selector::GreaterThan => Selector::gt,
             Selector::GreaterThanOrEqualTo => Selector::ge,
+            Selector::CompatibleRelease => Selector::compatible,
         }
     }
     fn eq(a: &str, b: &str) -> bool {
-        compare_to(a, b, Cmp::Eq).unwrap_or(false)
+        // A bare `*` version, like in the `python *` depends entries, matches any version
+        if b == "*" {
+            return true;
+        }
+        // `1.19.*` matches the whole 1.19 series, including 1.19 itself
+        if let Some(prefix) = b.strip_suffix(".*").filter(|p| !p.contains('*')) {
+            let a = strip_build_metadata(a);
+            return Selector::eq(a, prefix)
+                || a.strip_prefix(prefix).map(|rest| rest.starts_with(['.', '_'])).unwrap_or(false);
+        }
+        if b.contains('*') {
+            return is_match_glob_str(b, strip_build_metadata(a));
+        }
+        compare_versions(a, b) == Ordering::Equal
+    }
+
+    fn exactly_eq(a: &str, b: &str) -> bool {
+        let ((a, a_metadata), (b, b_metadata)) = (split_build_metadata(a), split_build_metadata(b));
+        a_metadata == b_metadata && compare_versions(a, b) == Ordering::Equal
     }
 
     fn ne(a: &str, b: &str) -> bool {
-        compare_to(a, b, Cmp::Ne).unwrap_or(false)
+        // `!=1.4.*` rules out the whole 1.4 series, the same versions `==1.4.*` accepts
+        if b.contains('*') {
+            return !Selector::eq(a, b);
+        }
+        compare_versions(a, b) != Ordering::Equal
     }
     fn lt(a: &str, b: &str) -> bool {
-        compare_to(a, b, Cmp::Lt).unwrap_or(false)
+        compare_versions(a, b) == Ordering::Less
     }
     fn le(a: &str, b: &str) -> bool {
-        compare_to(a, b, Cmp::Le).unwrap_or(false)
+        compare_versions(a, b) != Ordering::Greater
     }
     fn gt(a: &str, b: &str) -> bool {
-        compare_to(a, b, Cmp::Gt).unwrap_or(false)
+        compare_versions(a, b) == Ordering::Greater
     }
     fn ge(a: &str, b: &str) -> bool {
-        compare_to(a, b, Cmp::Ge).unwrap_or(false)
+        compare_versions(a, b) != Ordering::Less
+    }
+    fn compatible(a: &str, b: &str) -> bool {
+        // Without an upper bound only the lower bound can be checked, the parser rejects these
+        Selector::ge(a, b)
+            && compatible_upper_bound(b)
+                .map(|upper| Selector::lt(a, &upper))
+                .unwrap_or(true)
+    }
+
+    /// Checks the ordering of two values that aren't versions, `ordering` being the result of
+    /// comparing the candidate's value to the spec's value.
+    pub(crate) fn is_ordering_match(&self, ordering: Ordering) -> bool {
+        match self {
+            Selector::EqualTo | Selector::ExactlyEqualTo => ordering == Ordering::Equal,
+            Selector::NotEqualTo => ordering != Ordering::Equal,
+            Selector::LessThan => ordering == Ordering::Less,
+            Selector::LessThanOrEqualTo => ordering != Ordering::Greater,
+            Selector::GreaterThan => ordering == Ordering::Greater,
+            Selector::GreaterThanOrEqualTo | Selector::CompatibleRelease => ordering != Ordering::Less,
+        }
     }
 }
 
@@ -224,6 +410,240 @@ impl<S> CompoundSelector<S>
             }
         }
     }
+
+    /// Returns every (selector, version) pair in this CompoundSelector
+    /// ```
+    /// use rust_matchspec::{Selector, CompoundSelector};
+    ///
+    /// let cs: CompoundSelector<String> = ">=1.0,<2.0".parse().unwrap();
+    /// assert_eq!(cs.selectors(), vec![(&Selector::GreaterThanOrEqualTo, "1.0"), (&Selector::LessThan, "2.0")]);
+    /// ```
+    pub fn selectors(&self) -> Vec<(&Selector, &str)> {
+        match self {
+            CompoundSelector::Single { selector, version } => vec![(selector, version.as_ref())],
+            CompoundSelector::And {
+                first_selector,
+                first_version,
+                second_selector,
+                second_version,
+            }
+            | CompoundSelector::Or {
+                first_selector,
+                first_version,
+                second_selector,
+                second_version,
+            } => vec![
+                (first_selector, first_version.as_ref()),
+                (second_selector, second_version.as_ref()),
+            ],
+        }
+    }
+}
+
+/// Parses a bare version constraint without a package name:
+/// ```
+/// use rust_matchspec::{Selector, CompoundSelector};
+///
+/// let cs: CompoundSelector<String> = ">=1.2,<2.0".parse().unwrap();
+/// assert!(cs.is_match("1.5"));
+/// assert!(!cs.is_match("2.1"));
+/// ```
+impl FromStr for CompoundSelector<String> {
+    type Err = MatchSpecError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match terminated(compound_selector_parser, eof)(s).finish() {
+            Ok((_, cs)) => Ok(cs),
+            Err(NomError { input, code: _ }) => Err(MatchSpecError {
+                message: String::from(input),
+            }),
+        }
+    }
+}
+
+/// Renders the CompoundSelector in the same form that it is parsed from:
+/// ```
+/// use rust_matchspec::CompoundSelector;
+///
+/// let cs = CompoundSelector::from((">=", "1.2", ",", "<", "2.0"));
+/// assert_eq!(cs.to_string(), ">=1.2,<2.0");
+/// ```
+impl<S> Display for CompoundSelector<S>
+    where
+        S: Into<String> + AsRef<str>,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompoundSelector::Single { selector, version } => {
+                write!(f, "{}{}", selector, version.as_ref())
+            }
+            CompoundSelector::And {
+                first_selector,
+                first_version,
+                second_selector,
+                second_version,
+            } => write!(
+                f,
+                "{}{},{}{}",
+                first_selector,
+                first_version.as_ref(),
+                second_selector,
+                second_version.as_ref()
+            ),
+            CompoundSelector::Or {
+                first_selector,
+                first_version,
+                second_selector,
+                second_version,
+            } => write!(
+                f,
+                "{}{}|{}{}",
+                first_selector,
+                first_version.as_ref(),
+                second_selector,
+                second_version.as_ref()
+            ),
+        }
+    }
+}
+
+/// Copies a borrowed CompoundSelector into an owned one
+impl From<CompoundSelector<&str>> for CompoundSelector<String> {
+    fn from(cs: CompoundSelector<&str>) -> Self {
+        match cs {
+            CompoundSelector::Single { selector, version } => CompoundSelector::Single {
+                selector,
+                version: version.into(),
+            },
+            CompoundSelector::And { first_selector, first_version, second_selector, second_version } => CompoundSelector::And {
+                first_selector,
+                first_version: first_version.into(),
+                second_selector,
+                second_version: second_version.into(),
+            },
+            CompoundSelector::Or { first_selector, first_version, second_selector, second_version } => CompoundSelector::Or {
+                first_selector,
+                first_version: first_version.into(),
+                second_selector,
+                second_version: second_version.into(),
+            },
+        }
+    }
+}
+
+impl CompoundSelector<String> {
+    /// Returns a CompoundSelector that borrows its versions from this one
+    pub fn as_borrowed(&self) -> CompoundSelector<&str> {
+        match self {
+            CompoundSelector::Single { selector, version } => CompoundSelector::Single {
+                selector: selector.clone(),
+                version,
+            },
+            CompoundSelector::And { first_selector, first_version, second_selector, second_version } => CompoundSelector::And {
+                first_selector: first_selector.clone(),
+                first_version,
+                second_selector: second_selector.clone(),
+                second_version,
+            },
+            CompoundSelector::Or { first_selector, first_version, second_selector, second_version } => CompoundSelector::Or {
+                first_selector: first_selector.clone(),
+                first_version,
+                second_selector: second_selector.clone(),
+                second_version,
+            },
+        }
+    }
+
+    /// Tests whether the range of versions matched by this CompoundSelector intersects with the
+    /// range matched by another.
+    /// ```
+    /// use rust_matchspec::CompoundSelector;
+    ///
+    /// let a: CompoundSelector<String> = ">=1.0,<2.0".parse().unwrap();
+    /// let b: CompoundSelector<String> = ">=1.5".parse().unwrap();
+    /// assert!(a.version_range_overlaps(&b));
+    /// ```
+    pub fn version_range_overlaps(&self, other: &CompoundSelector<String>) -> bool {
+        overlaps(self, other)
+    }
+
+    /// Structural equality where the versions are compared as versions rather than strings, so
+    /// `==1.0` and `==1.0.0` are equivalent.
+    pub fn semantically_eq(&self, other: &CompoundSelector<String>) -> bool {
+        let eq = Selector::eq;
+        match (self, other) {
+            (
+                CompoundSelector::Single { selector, version },
+                CompoundSelector::Single { selector: other_selector, version: other_version },
+            ) => selector == other_selector && eq(version, other_version),
+            (
+                CompoundSelector::And { first_selector, first_version, second_selector, second_version },
+                CompoundSelector::And {
+                    first_selector: other_first_selector,
+                    first_version: other_first_version,
+                    second_selector: other_second_selector,
+                    second_version: other_second_version,
+                },
+            )
+            | (
+                CompoundSelector::Or { first_selector, first_version, second_selector, second_version },
+                CompoundSelector::Or {
+                    first_selector: other_first_selector,
+                    first_version: other_first_version,
+                    second_selector: other_second_selector,
+                    second_version: other_second_version,
+                },
+            ) => {
+                first_selector == other_first_selector
+                    && eq(first_version, other_first_version)
+                    && second_selector == other_second_selector
+                    && eq(second_version, other_second_version)
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes a clause of an `And` that is implied by the other one, so `>=1.0,>=1.2` becomes
+    /// `>=1.2` and `==1.5,<2.0` becomes `==1.5`. Anything else is returned unchanged.
+    /// ```
+    /// use rust_matchspec::CompoundSelector;
+    ///
+    /// let cs: CompoundSelector<String> = ">=1.0,>=1.2".parse().unwrap();
+    /// assert_eq!(cs.simplify().to_string(), ">=1.2");
+    /// ```
+    pub fn simplify(&self) -> CompoundSelector<String> {
+        let CompoundSelector::And { first_selector, first_version, second_selector, second_version } = self else {
+            return self.clone();
+        };
+        let first = CompoundSelector::Single { selector: first_selector.clone(), version: first_version.clone() };
+        let second = CompoundSelector::Single { selector: second_selector.clone(), version: second_version.clone() };
+
+        let is_lower = |s: &Selector| matches!(s, Selector::GreaterThan | Selector::GreaterThanOrEqualTo);
+        let is_upper = |s: &Selector| matches!(s, Selector::LessThan | Selector::LessThanOrEqualTo);
+        let is_exact = |s: &Selector| matches!(s, Selector::EqualTo | Selector::ExactlyEqualTo);
+        let is_strict = |s: &Selector| matches!(s, Selector::GreaterThan | Selector::LessThan);
+
+        // For two bounds on the same side, keep the tighter one. Equal versions keep the strict one.
+        let tighter = |keep_when: Ordering| match compare_versions(first_version, second_version) {
+            Ordering::Equal if is_strict(second_selector) => second.clone(),
+            Ordering::Equal => first.clone(),
+            ordering if ordering == keep_when => first.clone(),
+            _ => second.clone(),
+        };
+
+        if first_selector == second_selector && Selector::eq(first_version, second_version) {
+            first
+        } else if is_lower(first_selector) && is_lower(second_selector) {
+            tighter(Ordering::Greater)
+        } else if is_upper(first_selector) && is_upper(second_selector) {
+            tighter(Ordering::Less)
+        } else if is_exact(first_selector) && second.is_match(first_version) {
+            first
+        } else if is_exact(second_selector) && first.is_match(second_version) {
+            second
+        } else {
+            self.clone()
+        }
+    }
 }
 
 /// Create a selector from a parser tuple:
@@ -276,15 +696,18 @@ impl<S, V> From<(S, V, V, S, V)> for CompoundSelector<String>
 /// Here are some examples in real usage.
 /// ```bash
 /// openssl>=1.1.1g
-/// openssl>=1.1.1g[platform='linux-64']
+/// openssl>=1.1.1g[subdir=linux-64]
 /// tensorflow==2.9.*
 /// ```
 /// Full MatchSpec documentation is found in the code [here](https://github.com/conda/conda/blob/main/conda/models/match_spec.py)
 /// and [here](https://conda.io/projects/conda-build/en/latest/resources/package-spec.html#build-version-spec) in the spec
 #[pyclass]
-#[derive(Debug, Clone, Eq)]
+#[derive(Clone, Eq)]
 pub struct MatchSpec {
     pub channel: Option<String>,
+    /// Every channel of a channel list like `conda-forge,defaults::numpy`, highest priority first.
+    /// `channel` holds the first of them. Empty when only one channel was given.
+    pub channels: Vec<String>,
     pub subdir: Option<String>,
     pub namespace: Option<String>,
     pub package: String,
@@ -292,6 +715,12 @@ pub struct MatchSpec {
     pub build: Option<String>,
     pub build_number: Option<CompoundSelector<String>>,
     pub key_value_pairs: Vec<(String, CompoundSelector<String>)>,
+    /// Package name globs that are rejected even if `package` matches, set via `[exclude=...]`
+    pub exclude: Vec<String>,
+    /// When set the package name is compared verbatim, so a `*` in it isn't treated as a glob
+    pub literal_package: bool,
+    /// The trimmed string this MatchSpec was parsed from, see `MatchSpec::raw`
+    pub(crate) raw: Option<String>,
 }
 
 /// Custom implementation to make sure that we don't compare key_value_pairs
@@ -300,11 +729,14 @@ pub struct MatchSpec {
 impl PartialEq for MatchSpec {
     fn eq(&self, other: &Self) -> bool {
         self.channel == other.channel
+            && self.channels == other.channels
             && self.subdir == other.subdir
             && self.namespace == other.namespace
             && self.package == other.package
             && self.version == other.version
             && self.build == other.build
+            && self.exclude == other.exclude
+            && self.literal_package == other.literal_package
     }
 }
 
@@ -312,6 +744,7 @@ impl Default for MatchSpec {
     fn default() -> Self {
         MatchSpec {
             channel: None,
+            channels: Vec::new(),
             subdir: None,
             namespace: None,
             package: "*".to_string(),
@@ -319,251 +752,3736 @@ impl Default for MatchSpec {
             build: None,
             build_number: None,
             key_value_pairs: Vec::new(),
+            exclude: Vec::new(),
+            literal_package: false,
+            raw: None,
         }
     }
 }
 
-/// This is where we actually do the parsing
-impl FromStr for MatchSpec {
-    type Err = MatchSpecError;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match alt((implicit_matchspec_parser, full_matchspec_parser))(s).finish() {
-            Ok((_, ms)) => Ok(ms),
-            Err(NomError { input, code: _ }) => Err(MatchSpecError {
-                message: String::from(input),
-            }),
+/// Options that control how a MatchSpec is parsed. `FromStr` uses `ParseOptions::default()`,
+/// use `MatchSpec::parse_with` to change them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Inputs longer than this many bytes are rejected without being parsed
+    pub max_length: usize,
+    /// Maximum number of `,` or `|` separated clauses in the input. Separators in quoted values,
+    /// a channel list or a trailing comment don't count.
+    pub max_clauses: usize,
+    /// Match the package name verbatim instead of treating `*` as a glob, see
+    /// `MatchSpec::literal_package`
+    pub literal_package: bool,
+    /// Accept the English form `numpy not 1.19.*`, which is the same as `numpy!=1.19.*`
+    pub not_keyword: bool,
+    /// Rewrite informal subdirs like `x86_64` or `osx-arm` to the conda subdir, see
+    /// `normalize_subdir`
+    pub normalize_subdir: bool,
+    /// Reject bracketed keys that aren't in `KNOWN_KEYS`, which catches typos like
+    /// `[subdr=linux-64]`. Unknown keys are normally kept but ignored.
+    pub strict_keys: bool,
+    /// Accept the version before the name, like `1.20 numpy`, which some old tooling wrote
+    pub legacy_version_first: bool,
+    /// Read a numeric `+` suffix on an `==` version as the build_number, so `pkg==1.0+3` is
+    /// `pkg==1.0[build_number=3]`. Otherwise it is `+build` metadata on the version.
+    pub plus_build_number: bool,
+}
+
+/// The defaults are far beyond anything found in real repodata, but still bound the amount of
+/// work done on untrusted input.
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            max_length: 4096,
+            max_clauses: 256,
+            literal_package: false,
+            not_keyword: false,
+            normalize_subdir: false,
+            strict_keys: false,
+            legacy_version_first: false,
+            plus_build_number: false,
         }
     }
 }
 
-impl From<(&str, Option<&str>, Option<&str>)> for MatchSpec {
-    fn from((package, version, build): (&str, Option<&str>, Option<&str>)) -> Self {
-        MatchSpec {
-            channel: None,
-            subdir: None,
-            namespace: None,
-            package: package.into(),
-            version: version.map(|s| CompoundSelector::Single {
-                selector: Selector::EqualTo,
-                version: s.into(),
-            }),
-            build: build.map(|s| s.into()),
-            build_number: None,
-            key_value_pairs: Vec::new(),
-        }
+/// The bracketed keys conda knows about, which are the only ones allowed with
+/// `ParseOptions::strict_keys`
+pub const KNOWN_KEYS: [&str; 21] = [
+    "arch",
+    "build",
+    "build_number",
+    "channel",
+    "exclude",
+    "extension",
+    "features",
+    "fn",
+    "license",
+    "license_family",
+    "md5",
+    "name",
+    "namespace",
+    "platform",
+    "sha256",
+    "size",
+    "subdir",
+    "timestamp",
+    "track_features",
+    "url",
+    "version",
+];
+
+/// Quotes a key value if it has characters that can't be written bare inside the brackets, like
+/// spaces, commas or brackets. Double quotes are used if the value has a single quote in it.
+/// Values that don't need quoting are returned unchanged. Quoted values can't hold escapes, so a
+/// value with both kinds of quote can't be written and None is returned.
+/// ```
+/// use rust_matchspec::escape_value;
+///
+/// assert_eq!(escape_value("py39h_0"), Some("py39h_0".to_string()));
+/// assert_eq!(escape_value("Apache, 2.0"), Some("'Apache, 2.0'".to_string()));
+/// assert_eq!(escape_value("it's"), Some("\"it's\"".to_string()));
+/// assert_eq!(escape_value("it's \"x\""), None);
+/// ```
+pub fn escape_value(s: &str) -> Option<String> {
+    if s.chars().all(is_any_valid_str_with_glob) {
+        Some(s.to_string())
+    } else if !s.contains('\'') {
+        Some(format!("'{}'", s))
+    } else if !s.contains('"') {
+        Some(format!("\"{}\"", s))
+    } else {
+        None
     }
 }
 
-impl
-From<(
-    Option<&str>,
-    Option<&str>,
-    Option<&str>,
-    &str,
-    Option<CompoundSelector<String>>,
-    Option<Vec<(&str, CompoundSelector<String>)>>,
-)> for MatchSpec
-{
-    fn from(
-        (channel, subdir, ns, package, cs, keys): (
-            Option<&str>,
-            Option<&str>,
-            Option<&str>,
-            &str,
-            Option<CompoundSelector<String>>,
-            Option<Vec<(&str, CompoundSelector<String>)>>,
-        ),
-    ) -> Self {
-        let namespace = if let Some(a) = ns {
-            if a.is_empty() {
-                None
-            } else {
-                Some(a.to_string())
-            }
-        } else {
-            None
-        };
+/// Maps the informal names people use for a platform to the conda subdir, like `x86_64` to
+/// `linux-64` or `macos-arm64` to `osx-arm64`. A bare architecture is assumed to be linux. Subdirs
+/// that aren't known aliases are returned unchanged.
+/// ```
+/// use rust_matchspec::normalize_subdir;
+///
+/// assert_eq!(normalize_subdir("x86_64"), "linux-64");
+/// assert_eq!(normalize_subdir("win-amd64"), "win-64");
+/// assert_eq!(normalize_subdir("linux-ppc64le"), "linux-ppc64le");
+/// ```
+pub fn normalize_subdir(subdir: &str) -> &str {
+    let (platform, arch) = subdir.split_once('-').unwrap_or(("linux", subdir));
+    let platform = match platform {
+        "linux" => "linux",
+        "osx" | "macos" | "darwin" => "osx",
+        "win" | "windows" => "win",
+        _ => return subdir,
+    };
+    let arch = match arch {
+        "64" | "x86_64" | "amd64" | "x64" => "64",
+        "32" | "x86" | "i386" | "i686" => "32",
+        "arm64" | "aarch64" if platform == "osx" => "arm64",
+        "arm64" | "aarch64" => "aarch64",
+        "arm" if platform == "osx" => "arm64",
+        _ => return subdir,
+    };
+    match (platform, arch) {
+        ("linux", "64") => "linux-64",
+        ("linux", "32") => "linux-32",
+        ("linux", _) => "linux-aarch64",
+        ("osx", "64") => "osx-64",
+        ("osx", "arm64") => "osx-arm64",
+        ("win", "64") => "win-64",
+        ("win", "32") => "win-32",
+        ("win", _) => "win-arm64",
+        _ => subdir,
+    }
+}
 
-        // Create the initial struct based on the parsed tuple
-        let mut ms = MatchSpec {
-            channel: channel.map(|s| s.into()),
-            subdir: subdir.map(|s| s.into()),
-            namespace,
-            package: package.into(),
-            version: cs,
-            build: None,
-            build_number: None,
-            key_value_pairs: Vec::new(),
-        };
+/// Lowercases the string when matching case insensitively, otherwise it's borrowed unchanged
+fn fold_case(s: &str, case_insensitive: bool) -> Cow<'_, str> {
+    if case_insensitive {
+        Cow::Owned(s.to_lowercase())
+    } else {
+        Cow::Borrowed(s)
+    }
+}
 
-        // Convert the key_value_pairs into (S, CompoundSelector) tuples.
-        // I'm not sure its possible to have the full selector set, but this models it in a
-        // pretty good way.
-        let key_value_pairs: Vec<(String, CompoundSelector<String>)> = keys.map(|vec| {
-            vec.iter().map(|(key, value)| {
-                (String::from(*key), value.clone())
-            }).collect()
-        }).unwrap_or_default();
+/// Options that control how lenient `MatchSpec::is_match_with` is. The defaults give the same
+/// result as `MatchSpec::is_match`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchOptions {
+    /// Compare package names ignoring ASCII and unicode case
+    pub case_insensitive: bool,
+    /// Skip the build string check
+    pub ignore_build: bool,
+    /// Allow candidates with pre-release versions like `2.0rc1`. When false they are only matched
+    /// if the spec itself names a pre-release version.
+    pub allow_prerelease: bool,
+    /// Skip checking the spec's channel against the candidate's. True by default, so plain
+    /// `is_match` doesn't depend on where a candidate came from. Set it to false to have
+    /// `conda-forge::numpy` rule out candidates from other channels.
+    pub ignore_channel: bool,
+}
 
-        // Lets set the final attributes based on the key value pairs
-        // Currently we only support EqualTo relations, but maybe in the future we can fix that.
-        for (key, compound_selector) in &key_value_pairs {
-            // TODO: here
-            match (key.as_ref(), compound_selector) {
-                ("build", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.build = Some(version.clone()),
-                ("channel", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.channel = Some(version.clone()),
-                ("subdir", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.subdir = Some(version.clone()),
-                ("namepsace", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.namespace = Some(version.clone()),
-                ("build_number", CompoundSelector::Single { selector: _, version: _ }) => ms.build_number = Some(compound_selector.clone()),
-                _ => (),
-            }
+impl Default for MatchOptions {
+    fn default() -> Self {
+        MatchOptions {
+            case_insensitive: false,
+            ignore_build: false,
+            allow_prerelease: true,
+            ignore_channel: true,
         }
-
-        // Save all the key value pairs, this is done last to avoid borrow after move
-        ms.key_value_pairs = key_value_pairs;
-        ms
     }
 }
 
-impl MatchSpec {
-    /// Matches package names. The matchspec package may contain globs
-    /// ```
-    /// use rust_matchspec::matchspec::*;
-    ///
-    /// let ms: MatchSpec = "openssl>1.1.1a".parse().unwrap();
-    /// assert!(ms.is_package_match("openssl".to_string()));
-    /// ```
-    pub fn is_package_match(&self, package: String) -> bool {
-        package.chars().all(is_alphanumeric_with_dashes)
-            && is_match_glob_str(self.package.as_ref(), package.as_ref())
-    }
+/// The name matching shared by MatchSpec and MatchSpecRef, so the two can't drift apart
+pub(crate) fn is_name_match<S: AsRef<str>>(
+    glob: &str,
+    exclude: &[S],
+    literal_package: bool,
+    package: &str,
+    case_insensitive: bool,
+) -> bool {
+    let fold = |s| fold_case(s, case_insensitive);
+    let (glob, package) = (fold(glob), fold(package));
 
-    /// Uses the Selector embedded in the matchspec to do a match on only a version
-    /// ```
-    /// use rust_matchspec::matchspec::*;
-    ///
-    /// let ms: MatchSpec = "openssl>1.1.1a".parse().unwrap();
-    /// assert!(ms.is_version_match(&"1.1.1r"));
-    /// ```
-    pub fn is_version_match(&self, version: &str) -> bool {
-        self.version
-            .as_ref()
-            .map(|v| v.is_match(version))
-            .unwrap_or(true)
+    let matches = if literal_package {
+        glob == package
+    } else {
+        package.chars().all(is_alphanumeric_with_dashes) && is_match_glob_str(&glob, &package)
+    };
+    matches && !exclude.iter().any(|exclude| is_match_glob_str(&fold(exclude.as_ref()), &package))
+}
+
+/// The version matching shared by MatchSpec and MatchSpecRef. A candidate version like `1.2.*`
+/// is the whole series, and matches if any version in it would.
+pub(crate) fn is_version_match<S>(cs: Option<&CompoundSelector<S>>, version: &str) -> bool
+where
+    S: AsRef<str> + PartialEq + Into<String>,
+{
+    let Some(cs) = cs else {
+        return true;
+    };
+    if version.ends_with(".*") && !version[..version.len() - 2].contains('*') {
+        return overlaps(cs, &CompoundSelector::<&str>::Single { selector: Selector::EqualTo, version });
     }
+    cs.is_match(version)
+}
 
-    pub fn is_package_version_match(&self, package: &str, version: &str) -> bool {
-        package.chars().all(is_alphanumeric_with_dashes)
-            && is_match_glob_str(self.package.as_ref(), package)
-            && self.is_version_match(version)
+/// Removes a trailing `# comment`, like the ones allowed in environment.yml files. A `#` inside of
+/// a quoted value isn't treated as a comment.
+pub(crate) fn strip_comment(s: &str) -> &str {
+    let mut quote: Option<char> = None;
+    for (i, c) in s.char_indices() {
+        match (c, quote) {
+            ('#', None) => return s[..i].trim_end(),
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => (),
+        }
     }
+    s.trim_end()
 }
 
-impl MatchSpec {
-    pub fn is_match(&self, pc: &PackageCandidate) -> bool {
-        let is_equal = |a: &Option<String>, b: &Option<String>| a.is_none() || a == b;
+/// Operators that are easy to get backwards, and what was most likely meant
+const OPERATOR_TYPOS: [(&str, &str); 3] = [("=>", ">="), ("=<", "<="), (">>", ">")];
 
-        self.is_package_version_match(&pc.name, pc.version.as_ref().unwrap_or(&String::new()))
-            && self.is_build_number_match(&pc.build_number)
-            && is_equal(&self.subdir, &pc.subdir)
-            && is_equal(&self.build, &pc.build)
+/// Finds the first operator typo in the spec, returning where it starts and the operator that was
+/// probably meant. Otherwise `numpy=>1.0` would fail with an error about the version. Only the
+/// version part of the spec is checked, so a quoted or bracketed value like
+/// `[url='https://a/b=>c']` can contain anything.
+fn operator_typo(s: &str) -> Option<(usize, &'static str)> {
+    let mut quote: Option<char> = None;
+    let mut brackets = 0usize;
+    for (i, c) in s.char_indices() {
+        match (c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => (),
+            ('\'' | '"', None) => quote = Some(c),
+            ('[', None) => brackets += 1,
+            (']', None) => brackets = brackets.saturating_sub(1),
+            _ if brackets == 0 => {
+                if let Some((_, operator)) = OPERATOR_TYPOS.iter().find(|(typo, _)| s[i..].starts_with(typo)) {
+                    return Some((i, operator));
+                }
+            }
+            _ => (),
+        }
     }
+    None
+}
 
-    pub fn is_build_number_match(&self, build_number: &Option<u32>) -> bool {
-        match build_number {
-            Some(number) => {
-                self.build_number
-                    .as_ref()
-                    .map(|v| v.is_match(&number.to_string()))
-                    .unwrap_or(true)
-            }
-            None => true
+/// Counts the `,` and `|` separated clauses, for `ParseOptions::max_clauses`. A `||` is one
+/// separator, and separators in quoted values or a `channel,channel::` prefix are skipped.
+fn count_clauses(s: &str) -> usize {
+    let s = match s.find(':') {
+        Some(i) if s[..i].chars().all(|c| is_alphanumeric_with_dashes(c) || is_comma(c) || c == '/') => &s[i..],
+        _ => s,
+    };
+
+    let mut quote: Option<char> = None;
+    let mut previous: Option<char> = None;
+    let mut clauses = 1;
+    for c in s.chars() {
+        match (c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => (),
+            ('\'' | '"', None) => quote = Some(c),
+            ('|', None) if previous == Some('|') => (),
+            (c, None) if is_comma_or_alt(c) => clauses += 1,
+            _ => (),
         }
+        previous = Some(c);
     }
+    clauses
 }
 
-#[cfg(test)]
-mod test {
-    #[cfg(test)]
-    mod matching {
-        use crate::matchspec::*;
+/// Moves a leading version after the package name, so `1.20 numpy py39_0` becomes
+/// `numpy 1.20 py39_0`. None if the spec doesn't start with a version. Names like `7za` look like
+/// versions too, so when the next token is also a version, like in `7za 9.20`, the first one is
+/// taken to be the name and nothing is swapped.
+fn swap_leading_version(s: &str) -> Option<String> {
+    let looks_like_version =
+        |token: &str| token.starts_with(|c: char| c.is_ascii_digit()) && matches!(version_parser(token), Ok(("", _)));
+
+    let (version, rest) = s.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start();
+    let next = rest.split(char::is_whitespace).next().unwrap_or_default();
+    if !looks_like_version(version) || looks_like_version(next) {
+        return None;
+    }
+
+    match rest.split_once(char::is_whitespace) {
+        Some((package, build)) => Some(format!("{} {} {}", package, version, build.trim_start())),
+        None if !rest.is_empty() => Some(format!("{} {}", rest, version)),
+        None => None,
+    }
+}
+
+/// Enforces the size limits from ParseOptions, rejects common operator typos and strips any
+/// trailing comment, returning the part of the input that should be parsed.
+fn check_limits<'a>(s: &'a str, options: &ParseOptions) -> Result<&'a str, MatchSpecError> {
+    if s.len() > options.max_length {
+        return Err(MatchSpecError {
+            message: format!(
+                "MatchSpec is {} bytes long, the limit is {}",
+                s.len(),
+                options.max_length
+            ),
+        });
+    }
+
+    let s = strip_comment(s);
+    let clauses = count_clauses(s);
+    if clauses > options.max_clauses {
+        return Err(MatchSpecError {
+            message: format!(
+                "MatchSpec has {} clauses, the limit is {}",
+                clauses, options.max_clauses
+            ),
+        });
+    }
+
+    if let Some((i, operator)) = operator_typo(s) {
+        let MatchSpecError { message } = parse_error(s, &s[i..]);
+        return Err(MatchSpecError {
+            message: format!("{}, did you mean `{}`?", message, operator),
+        });
+    }
+
+    Ok(s)
+}
+
+/// Builds the error for a failed parse. Nom only reports the unparsed remainder, so the original
+/// input and the offset of the remainder within it are added to make the error readable. Some
+/// parsers fail with a message instead of a remainder, in which case there's no offset.
+fn parse_error(original: &str, remainder: &str) -> MatchSpecError {
+    let range = original.as_bytes().as_ptr_range();
+    let message = if range.contains(&remainder.as_ptr()) || range.end == remainder.as_ptr() {
+        format!(
+            "Unable to parse MatchSpec '{}' at offset {}, remaining input '{}'",
+            original,
+            remainder.as_ptr() as usize - original.as_ptr() as usize,
+            remainder
+        )
+    } else {
+        format!("Unable to parse MatchSpec '{}': {}", original, remainder)
+    };
+    MatchSpecError { message }
+}
+
+/// This is where we actually do the parsing
+impl FromStr for MatchSpec {
+    type Err = MatchSpecError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MatchSpec::parse_with(s, &ParseOptions::default())
+    }
+}
+
+impl TryFrom<&str> for MatchSpec {
+    type Error = MatchSpecError;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for MatchSpec {
+    type Error = MatchSpecError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// MatchSpecs are deserialized from their string form, so they can be used as fields in config
+/// files:
+/// ```
+/// use rust_matchspec::MatchSpec;
+///
+/// let ms: MatchSpec = serde_json::from_str(r#""numpy>=1.20""#).unwrap();
+/// assert_eq!(ms.package, "numpy");
+/// assert!(serde_json::from_str::<MatchSpec>(r#""python=wrong""#).is_err());
+/// ```
+impl<'de> Deserialize<'de> for MatchSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+impl MatchSpec {
+    /// Parses a MatchSpec using the given options
+    /// ```
+    /// use rust_matchspec::{MatchSpec, ParseOptions};
+    ///
+    /// let options = ParseOptions { max_length: 8, ..ParseOptions::default() };
+    /// assert!(MatchSpec::parse_with("numpy", &options).is_ok());
+    /// assert!(MatchSpec::parse_with("numpy>=1.20", &options).is_err());
+    /// ```
+    pub fn parse_with(s: &str, options: &ParseOptions) -> Result<Self, MatchSpecError> {
+        let raw = s.trim();
+        let input = check_limits(s, options)?;
+        let swapped = options.legacy_version_first.then(|| swap_leading_version(input)).flatten();
+        let input = swapped.as_deref().unwrap_or(input);
+        let result = if options.not_keyword {
+            alt((map(not_keyword_matchspec_ref_parser, MatchSpec::from), implicit_matchspec_parser, full_matchspec_parser))(input)
+        } else {
+            alt((implicit_matchspec_parser, full_matchspec_parser))(input)
+        };
+        match result.finish() {
+            Ok((_, ms)) if options.strict_keys && ms.unknown_key().is_some() => Err(MatchSpecError {
+                message: format!("Unknown key '{}' in MatchSpec '{}'", ms.unknown_key().unwrap_or_default(), raw),
+            }),
+            Ok((_, ms)) => {
+                let ms = if options.plus_build_number { ms.split_plus_build_number() } else { ms };
+                Ok(MatchSpec {
+                    subdir: match ms.subdir {
+                        Some(subdir) if options.normalize_subdir => Some(normalize_subdir(&subdir).to_string()),
+                        subdir => subdir,
+                    },
+                    literal_package: options.literal_package,
+                    raw: Some(raw.to_string()),
+                    ..ms
+                })
+            }
+            Err(NomError { input, code: _ }) => Err(parse_error(s, input)),
+        }
+    }
+
+    /// Moves a numeric `+3` on an `==` version into the build_number, see
+    /// `ParseOptions::plus_build_number`. A build_number from the brackets is left alone.
+    fn split_plus_build_number(self) -> MatchSpec {
+        let split = match &self.version {
+            Some(CompoundSelector::Single { selector: Selector::EqualTo, version }) if self.build_number.is_none() => version
+                .split_once('+')
+                .filter(|(_, number)| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+                .map(|(version, number)| (version.to_string(), number.to_string())),
+            _ => None,
+        };
+        match split {
+            Some((version, number)) => MatchSpec {
+                version: Some(CompoundSelector::from(("=", version))),
+                build_number: Some(CompoundSelector::from(("=", number))),
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    /// The first bracketed key that isn't one of the `KNOWN_KEYS`
+    fn unknown_key(&self) -> Option<&str> {
+        self.key_value_pairs
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .find(|key| !KNOWN_KEYS.contains(key))
+    }
+
+    /// Checks whether the string is a valid MatchSpec, without building one. This is cheaper than
+    /// `parse` when validating lots of specs that aren't needed afterwards.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// assert!(MatchSpec::is_valid("numpy>=1.20"));
+    /// assert!(!MatchSpec::is_valid("python=wrong"));
+    /// ```
+    pub fn is_valid(s: &str) -> bool {
+        let options = ParseOptions::default();
+        s.len() <= options.max_length
+            && count_clauses(strip_comment(s)) <= options.max_clauses
+            && operator_typo(strip_comment(s)).is_none()
+            && alt((implicit_matchspec_ref_parser, full_matchspec_ref_parser))(strip_comment(s)).is_ok()
+    }
+
+    /// Parses only the package name out of a MatchSpec, which is much cheaper than parsing the
+    /// whole thing. The rest of the spec isn't validated, so this can succeed where `parse`
+    /// would fail.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let name = MatchSpec::parse_package_name("conda-forge/linux-64::numpy>=1.20[build=py39*]").unwrap();
+    /// assert_eq!(name, "numpy");
+    /// ```
+    pub fn parse_package_name(s: &str) -> Result<String, MatchSpecError> {
+        let input = check_limits(s, &ParseOptions::default())?;
+        match package_name_parser(input).finish() {
+            Ok((_, name)) => Ok(name.to_string()),
+            Err(NomError { input, code: _ }) => Err(parse_error(s, input)),
+        }
+    }
+
+    /// Parses a MatchSpec without copying any of its fields, the returned MatchSpecRef borrows
+    /// from `s`. This is cheaper than `parse` when the spec is only needed briefly.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms = MatchSpec::parse_borrowed("numpy>=1.20[build=py39*]").unwrap();
+    /// assert_eq!(ms.package, "numpy");
+    /// assert_eq!(ms.build, Some("py39*"));
+    /// ```
+    pub fn parse_borrowed(s: &str) -> Result<MatchSpecRef<'_>, MatchSpecError> {
+        let input = check_limits(s, &ParseOptions::default())?;
+        match alt((implicit_matchspec_ref_parser, full_matchspec_ref_parser))(input).finish() {
+            Ok((_, ms)) => Ok(ms),
+            Err(NomError { input, code: _ }) => Err(parse_error(s, input)),
+        }
+    }
+
+    /// Parses a pinned line from a conda-lock environment file, `name=version=build` with an
+    /// optional `channel(/subdir)::` prefix. A leading `- ` from the YAML list and a trailing
+    /// `# comment` are ignored. Anything that isn't an exact pin, like a missing build, a
+    /// version range or a glob, is an error.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms = MatchSpec::parse_lock_line("  - conda-forge/linux-64::numpy=1.21.0=py39hdbf815f_0").unwrap();
+    /// assert_eq!(ms.channel.as_deref(), Some("conda-forge"));
+    /// assert_eq!(ms.build.as_deref(), Some("py39hdbf815f_0"));
+    /// assert!(MatchSpec::parse_lock_line("numpy>=1.21").is_err());
+    /// ```
+    pub fn parse_lock_line(s: &str) -> Result<MatchSpec, MatchSpecError> {
+        let line = strip_comment(s.trim());
+        let line = line.strip_prefix('-').map(str::trim_start).unwrap_or(line);
+        let not_pinned = || MatchSpecError {
+            message: format!("'{}' is not a pinned name=version=build line", line),
+        };
+
+        let pin = line.rsplit_once("::").map_or(line, |(_, pin)| pin);
+        let parts: Vec<&str> = pin.split('=').collect();
+        let [_, version, build] = parts[..] else {
+            return Err(not_pinned());
+        };
+        if parts.iter().any(|part| part.is_empty() || part.contains(|c: char| c == '*' || c.is_whitespace())) {
+            return Err(not_pinned());
+        }
+
+        let ms: MatchSpec = line.parse()?;
+        let exact_version = matches!(
+            &ms.version,
+            Some(CompoundSelector::Single { selector: Selector::EqualTo, version: v }) if v == version
+        );
+        if !exact_version || ms.build.as_deref() != Some(build) {
+            return Err(not_pinned());
+        }
+        Ok(ms)
+    }
+
+    /// Parses as much of the MatchSpec as possible instead of failing, returning whatever is
+    /// left over that couldn't be parsed. If not even the package name can be parsed the
+    /// MatchSpec has an empty package, which matches nothing, and the whole input is returned.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let (ms, remainder) = MatchSpec::parse_lenient("numpy>=1.20 ;garbage");
+    /// assert_eq!(ms.to_string(), "numpy>=1.20");
+    /// assert_eq!(remainder.as_deref(), Some(";garbage"));
+    /// ```
+    pub fn parse_lenient(s: &str) -> (MatchSpec, Option<String>) {
+        let input = strip_comment(s.trim());
+        let parsed = alt((implicit_matchspec_parser, full_matchspec_parser))(input)
+            // Fall back to only the name, for something like a version that doesn't parse
+            .or_else(|_| name_parser(input).map(|(remainder, package)| (remainder, MatchSpec::from((package, None, None)))));
+
+        match parsed {
+            Ok((remainder, ms)) => {
+                let remainder = remainder.trim();
+                let consumed = input[..input.len() - remainder.len()].trim();
+                (
+                    MatchSpec {
+                        raw: Some(consumed.to_string()),
+                        ..ms
+                    },
+                    (!remainder.is_empty()).then(|| remainder.to_string()),
+                )
+            }
+            Err(_) => (
+                MatchSpec {
+                    package: String::new(),
+                    ..MatchSpec::default()
+                },
+                (!input.is_empty()).then(|| input.to_string()),
+            ),
+        }
+    }
+}
+
+/// Renders the canonical form of the MatchSpec, which can be parsed back into an equal MatchSpec.
+/// Returns an error if a key value can't be written, see `escape_value`.
+/// ```
+/// use rust_matchspec::MatchSpec;
+///
+/// let ms: MatchSpec = "numpy 1.21.0 py39h_0".parse().unwrap();
+/// assert_eq!(ms.to_string(), "numpy==1.21.0[build=py39h_0]");
+/// ```
+impl Display for MatchSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.channel_prefix(), self.package)?;
+        if let Some(version) = &self.version {
+            write!(f, "{}", version)?;
+        }
+
+        let key_values = self.bracketed_key_values(true, escape_value).ok_or(std::fmt::Error)?;
+        if !key_values.is_empty() {
+            write!(f, "[{}]", key_values.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Debug output is the canonical form, which keeps test failures readable. The alternate `{:#?}`
+/// form lists every field instead, and is also used when the MatchSpec can't be displayed.
+/// ```
+/// use rust_matchspec::MatchSpec;
+///
+/// let ms: MatchSpec = "numpy>=1.20[build=py39*]".parse().unwrap();
+/// assert_eq!(format!("{:?}", ms), r#"MatchSpec("numpy>=1.20[build=py39*]")"#);
+/// assert!(format!("{:#?}", ms).contains("build_number: None"));
+/// ```
+impl Debug for MatchSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        let mut canonical = String::new();
+        if !f.alternate() && write!(canonical, "{}", self).is_ok() {
+            return f.debug_tuple("MatchSpec").field(&canonical).finish();
+        }
+        f.debug_struct("MatchSpec")
+            .field("channel", &self.channel)
+            .field("channels", &self.channels)
+            .field("subdir", &self.subdir)
+            .field("namespace", &self.namespace)
+            .field("package", &self.package)
+            .field("version", &self.version)
+            .field("build", &self.build)
+            .field("build_number", &self.build_number)
+            .field("key_value_pairs", &self.key_value_pairs)
+            .field("exclude", &self.exclude)
+            .field("literal_package", &self.literal_package)
+            .field("raw", &self.raw)
+            .finish()
+    }
+}
+
+impl From<(&str, Option<&str>, Option<&str>)> for MatchSpec {
+    fn from(tuple: (&str, Option<&str>, Option<&str>)) -> Self {
+        MatchSpecRef::from(tuple).into()
+    }
+}
+
+/// Builds a MatchSpec for just a package and version constraint, like one computed from a range
+/// ```
+/// use rust_matchspec::{CompoundSelector, MatchSpec};
+///
+/// let ms = MatchSpec::from(("numpy", ">=1.20,<2".parse::<CompoundSelector<String>>().unwrap()));
+/// assert_eq!(ms.to_string(), "numpy>=1.20,<2");
+/// ```
+impl From<(&str, CompoundSelector<String>)> for MatchSpec {
+    fn from((package, cs): (&str, CompoundSelector<String>)) -> Self {
+        MatchSpec::from((None, None, None, package, Some(cs), None))
+    }
+}
+
+impl
+From<(
+    Option<&str>,
+    Option<&str>,
+    Option<&str>,
+    &str,
+    Option<CompoundSelector<String>>,
+    Option<Vec<(&str, CompoundSelector<String>)>>,
+)> for MatchSpec
+{
+    fn from(
+        (channel, subdir, ns, package, cs, keys): (
+            Option<&str>,
+            Option<&str>,
+            Option<&str>,
+            &str,
+            Option<CompoundSelector<String>>,
+            Option<Vec<(&str, CompoundSelector<String>)>>,
+        ),
+    ) -> Self {
+        // The hoisting of key value pairs lives in MatchSpecRef, so borrow the owned selectors
+        // and go through there.
+        let keys = keys.as_ref().map(|vec| {
+            vec.iter()
+                .map(|(key, value)| (*key, value.as_borrowed()))
+                .collect()
+        });
+        MatchSpecRef::from((
+            channel,
+            subdir,
+            ns,
+            package,
+            cs.as_ref().map(CompoundSelector::as_borrowed),
+            keys,
+        ))
+        .into()
+    }
+}
+
+impl MatchSpec {
+    /// The original string this MatchSpec was parsed from, with surrounding whitespace trimmed.
+    /// MatchSpecs that weren't parsed, or were derived from another one, have no raw string.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy  1.21.0 \n".parse().unwrap();
+    /// assert_eq!(ms.raw(), Some("numpy  1.21.0"));
+    /// assert_eq!(MatchSpec::default().raw(), None);
+    /// ```
+    pub fn raw(&self) -> Option<&str> {
+        self.raw.as_deref()
+    }
+
+    /// The package name or glob this MatchSpec matches against
+    pub fn package_pattern(&self) -> &str {
+        &self.package
+    }
+
+    /// True if the package is matched as a glob rather than compared directly. Callers matching
+    /// lots of names can skip the glob matching for literal names and use a plain comparison.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "tensorflow*".parse().unwrap();
+    /// assert!(ms.package_has_glob());
+    /// assert!(!"tensorflow".parse::<MatchSpec>().unwrap().package_has_glob());
+    /// ```
+    pub fn package_has_glob(&self) -> bool {
+        !self.literal_package && has_glob(&self.package)
+    }
+
+    /// Matches package names. The matchspec package may contain globs, unless `literal_package`
+    /// is set in which case the name has to be exactly equal.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "openssl>1.1.1a".parse().unwrap();
+    /// assert!(ms.is_package_match("openssl".to_string()));
+    ///
+    /// let options = ParseOptions { literal_package: true, ..ParseOptions::default() };
+    /// let ms = MatchSpec::parse_with("open*", &options).unwrap();
+    /// assert!(ms.is_package_match("open*".to_string()));
+    /// assert!(!ms.is_package_match("openssl".to_string()));
+    /// ```
+    pub fn is_package_match(&self, package: String) -> bool {
+        self.is_name_match(&package, false)
+    }
+
+    fn is_name_match(&self, package: &str, case_insensitive: bool) -> bool {
+        self.is_name_match_against(&self.package, package, case_insensitive)
+    }
+
+    /// Matches a package name against the given name glob, along with the rest of this MatchSpec's
+    /// name options. This lets the name glob live outside of the MatchSpec, see InternedMatchSpec.
+    pub(crate) fn is_name_match_against(&self, glob: &str, package: &str, case_insensitive: bool) -> bool {
+        is_name_match(glob, &self.exclude, self.literal_package, package, case_insensitive)
+    }
+
+    /// Checks the package name against the exclude globs set by `[exclude=...]`
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "tensorflow*[exclude=tensorflow-gpu]".parse().unwrap();
+    /// assert!(ms.is_package_excluded("tensorflow-gpu"));
+    /// assert!(!ms.is_package_excluded("tensorflow-cpu"));
+    /// ```
+    pub fn is_package_excluded(&self, package: &str) -> bool {
+        self.exclude
+            .iter()
+            .any(|glob| is_match_glob_str(glob.as_ref(), package))
+    }
+
+    /// Uses the Selector embedded in the matchspec to do a match on only a version. A version like
+    /// `1.2.*` stands for the whole 1.2 series, and matches if any version in the series would.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "openssl>1.1.1a".parse().unwrap();
+    /// assert!(ms.is_version_match(&"1.1.1r"));
+    /// assert!(ms.is_version_match(&"1.1.*"));
+    /// ```
+    pub fn is_version_match(&self, version: &str) -> bool {
+        is_version_match(self.version.as_ref(), version)
+    }
+
+    /// The version operator, for specs with a single version constraint. None when there is no
+    /// version or it combines two constraints with `,` or `|`.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+    /// assert_eq!(ms.version_selector(), Some(&Selector::GreaterThanOrEqualTo));
+    /// ```
+    pub fn version_selector(&self) -> Option<&Selector> {
+        match &self.version {
+            Some(CompoundSelector::Single { selector, version: _ }) => Some(selector),
+            _ => None,
+        }
+    }
+
+    /// True for an explicit `*` version like `numpy=*` or `numpy *`, which accepts any version but
+    /// asks for the latest one. A spec without a version is not a latest request.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// assert!("numpy=*".parse::<MatchSpec>().unwrap().is_latest_request());
+    /// assert!(!"numpy".parse::<MatchSpec>().unwrap().is_latest_request());
+    /// ```
+    pub fn is_latest_request(&self) -> bool {
+        matches!(
+            &self.version,
+            Some(CompoundSelector::Single { selector: Selector::EqualTo, version }) if version == "*"
+        )
+    }
+
+    /// How specific the version constraint is, as the most dotted segments in any of its versions.
+    /// An epoch, a local version after `+` and a trailing `*` segment don't count. A spec without a
+    /// version is 0.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// assert_eq!("numpy>=1.20,<2".parse::<MatchSpec>().unwrap().version_specificity(), 2);
+    /// assert_eq!("numpy".parse::<MatchSpec>().unwrap().version_specificity(), 0);
+    /// ```
+    pub fn version_specificity(&self) -> usize {
+        let Some(version) = &self.version else {
+            return 0;
+        };
+        version
+            .selectors()
+            .into_iter()
+            .map(|(_, version)| {
+                let version = version.split_once('!').map_or(version, |(_, v)| v);
+                let version = version.split_once('+').map_or(version, |(v, _)| v);
+                version
+                    .split('.')
+                    .filter(|segment| !segment.is_empty() && *segment != "*")
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the key_value_pairs as a map for convenient lookup. If a key is repeated the last
+    /// value wins.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "openssl[subdir=linux-64, license=Apache]".parse().unwrap();
+    /// assert_eq!(ms.key_values().get("license"), Some(&&CompoundSelector::from(("=", "Apache"))));
+    /// ```
+    pub fn key_values(&self) -> HashMap<&str, &CompoundSelector<String>> {
+        self.key_value_pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+            .collect()
+    }
+
+    /// Returns the key_value_pairs that aren't one of the `KNOWN_KEYS`, like the environment marker
+    /// in `[python_version>=3.8]`. These are kept with their selector but are never used for
+    /// matching, so it's up to the caller to evaluate them.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "pkg[python_version>=3.8, subdir=linux-64]".parse().unwrap();
+    /// let markers = ms.markers();
+    /// assert_eq!(markers.len(), 1);
+    /// assert_eq!(markers[0].0, "python_version");
+    /// assert!(markers[0].1.is_match("3.10"));
+    /// ```
+    pub fn markers(&self) -> Vec<(&str, &CompoundSelector<String>)> {
+        self.key_value_pairs
+            .iter()
+            .map(|(key, cs)| (key.as_str(), cs))
+            .filter(|(key, _)| !KNOWN_KEYS.contains(key))
+            .collect()
+    }
+
+    /// Lists the keys that appear more than once in the key_value_pairs, in the order they first
+    /// appear. Only the last value of a repeated key is used, so these are usually mistakes.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy[subdir=linux-64, build=py39*, subdir=osx-64]".parse().unwrap();
+    /// assert_eq!(ms.duplicate_keys(), vec!["subdir"]);
+    /// ```
+    pub fn duplicate_keys(&self) -> Vec<&str> {
+        let mut seen: Vec<&str> = Vec::new();
+        let mut duplicates: Vec<&str> = Vec::new();
+        for (key, _) in &self.key_value_pairs {
+            if seen.contains(&key.as_str()) {
+                if !duplicates.contains(&key.as_str()) {
+                    duplicates.push(key);
+                }
+            } else {
+                seen.push(key);
+            }
+        }
+        duplicates
+    }
+
+    pub fn is_package_version_match(&self, package: &str, version: &str) -> bool {
+        self.is_name_match(package, false) && self.is_version_match(version)
+    }
+}
+
+impl MatchSpec {
+    /// Renders `channel/subdir:namespace:`, or nothing if there is no channel
+    fn channel_prefix(&self) -> String {
+        match &self.channel {
+            Some(channel) => format!(
+                "{}{}:{}:",
+                if self.channels.is_empty() { channel.clone() } else { self.channels.join(",") },
+                self.subdir.as_ref().map(|s| format!("/{}", s)).unwrap_or_default(),
+                self.namespace.as_deref().unwrap_or_default()
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// Renders everything that needs to go inside the `[...]` brackets as `key=value` strings.
+    /// Key values that were hoisted into struct fields are rendered from those fields. Returns None
+    /// if `escape` can't write one of the values.
+    fn bracketed_key_values(&self, include_build: bool, escape: fn(&str) -> Option<String>) -> Option<Vec<String>> {
+        let render = |cs: &CompoundSelector<String>| match cs {
+            CompoundSelector::Single { selector: Selector::EqualTo, version } => escape(version),
+            _ => escape(&cs.to_string()).map(|s| if s.starts_with(['\'', '"']) { s } else { format!("'{}'", s) }),
+        };
+        let is_hoisted = |key: &str, cs: &CompoundSelector<String>| {
+            matches!(
+                (key, cs),
+                ("build_number" | "version", _)
+                    | ("build" | "channel" | "subdir" | "namespace" | "exclude", CompoundSelector::Single { selector: Selector::EqualTo, version: _ })
+            )
+        };
+
+        let mut key_values: Vec<String> = Vec::new();
+        if let Some(build) = self.build.as_ref().filter(|_| include_build) {
+            key_values.push(format!("build={}", escape(build)?));
+        }
+        if let Some(build_number) = &self.build_number {
+            key_values.push(format!("build_number={}", render(build_number)?));
+        }
+        // Without a channel there is no prefix to hold these
+        if self.channel.is_none() {
+            if let Some(subdir) = &self.subdir {
+                key_values.push(format!("subdir={}", escape(subdir)?));
+            }
+            if let Some(namespace) = &self.namespace {
+                key_values.push(format!("namespace={}", escape(namespace)?));
+            }
+        }
+        for exclude in &self.exclude {
+            key_values.push(format!("exclude={}", escape(exclude)?));
+        }
+        for (key, cs) in self.key_value_pairs.iter().filter(|(k, cs)| !is_hoisted(k, cs)) {
+            key_values.push(format!("{}={}", key, render(cs)?));
+        }
+
+        Some(key_values)
+    }
+
+    /// A deterministic key for caching and deduplicating MatchSpecs across runs. Unlike `Display`
+    /// the key values are sorted, the clauses of a compound version are sorted, and versions are
+    /// normalized, so `numpy<2,>=1.20.0` and `numpy >=1.20,<2` have the same key. A literal
+    /// package name gets a `literal` entry, since it matches different packages than the glob.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let a: MatchSpec = "numpy>=1.20.0,<2[license=BSD,md5=abc]".parse().unwrap();
+    /// let b: MatchSpec = "numpy<2.0,>=1.20[md5=abc,license=BSD]".parse().unwrap();
+    /// assert_eq!(a.stable_key(), b.stable_key());
+    /// ```
+    pub fn stable_key(&self) -> String {
+        // Trailing zero segments don't change a version, so `1.20.0` is written as `1.20`
+        let normalize = |version: &str| -> String {
+            let version = version.strip_prefix("0!").unwrap_or(version).to_ascii_lowercase();
+            if version.contains(['*', '+']) {
+                return version;
+            }
+            let mut segments: Vec<&str> = version.split('.').collect();
+            while segments.len() > 1 && segments.last().map(|s| s.chars().all(|c| c == '0')).unwrap_or(false) {
+                segments.pop();
+            }
+            segments.join(".")
+        };
+        let clause = |selector: &Selector, version: &str| format!("{}{}", selector, normalize(version));
+        let sorted = |a: String, b: String, joiner: &str| {
+            let mut clauses = [a, b];
+            clauses.sort();
+            clauses.join(joiner)
+        };
+
+        let version = match self.version.as_ref().map(CompoundSelector::simplify) {
+            Some(CompoundSelector::Single { selector, version }) => clause(&selector, &version),
+            Some(CompoundSelector::And { first_selector, first_version, second_selector, second_version }) => {
+                sorted(clause(&first_selector, &first_version), clause(&second_selector, &second_version), ",")
+            }
+            Some(CompoundSelector::Or { first_selector, first_version, second_selector, second_version }) => {
+                sorted(clause(&first_selector, &first_version), clause(&second_selector, &second_version), "|")
+            }
+            None => String::new(),
+        };
+
+        // The key only has to be unique, so values that can't be escaped are debug quoted instead
+        let mut key_values = self
+            .bracketed_key_values(true, |value| escape_value(value).or_else(|| Some(format!("{:?}", value))))
+            .unwrap_or_default();
+        if self.literal_package {
+            key_values.push("literal".to_string());
+        }
+        key_values.sort();
+        format!("{}{}{}[{}]", self.channel_prefix(), self.package, version, key_values.join(","))
+    }
+
+    /// Renders the MatchSpec the way it would be written in a conda environment.yml. Unlike the
+    /// canonical `Display` form, an exact version and build are written as `name=version=build`
+    /// instead of using brackets. Returns None if a key value can't be written, see `escape_value`.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+    /// assert_eq!(ms.to_environment_yml_line().unwrap(), "numpy>=1.20");
+    ///
+    /// let ms: MatchSpec = "numpy 1.21.0 py39h_0".parse().unwrap();
+    /// assert_eq!(ms.to_environment_yml_line().unwrap(), "numpy=1.21.0=py39h_0");
+    /// ```
+    pub fn to_environment_yml_line(&self) -> Option<String> {
+        let mut line = format!("{}{}", self.channel_prefix(), self.package);
+
+        let build_inline = match (&self.version, &self.build) {
+            (Some(CompoundSelector::Single { selector: Selector::EqualTo, version }), Some(build))
+                if build.chars().all(is_any_valid_str_with_glob) =>
+            {
+                line.push_str(&format!("={}={}", version, build));
+                true
+            }
+            (Some(version), _) => {
+                line.push_str(&version.to_string());
+                false
+            }
+            (None, _) => false,
+        };
+
+        let key_values = self.bracketed_key_values(!build_inline, escape_value)?;
+        if !key_values.is_empty() {
+            line.push_str(&format!("[{}]", key_values.join(",")));
+        }
+        Some(line)
+    }
+
+    /// Converts the MatchSpec into a PEP 508 requirement for pip. Returns None when the spec uses
+    /// anything pip can't express: a channel, subdir, namespace, build, any bracketed key, a
+    /// package glob, or an `|` between versions.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20,<2.0".parse().unwrap();
+    /// assert_eq!(ms.to_pip_requirement(), Some("numpy>=1.20,<2.0".to_string()));
+    ///
+    /// let ms: MatchSpec = "conda-forge::numpy>=1.20".parse().unwrap();
+    /// assert_eq!(ms.to_pip_requirement(), None);
+    /// ```
+    pub fn to_pip_requirement(&self) -> Option<String> {
+        if self.channel.is_some()
+            || self.subdir.is_some()
+            || self.namespace.is_some()
+            || self.build.is_some()
+            || self.build_number.is_some()
+            || !self.key_value_pairs.is_empty()
+            || self.package.contains('*')
+        {
+            return None;
+        }
+
+        match &self.version {
+            None => Some(self.package.clone()),
+            Some(CompoundSelector::Or { .. }) => None,
+            Some(CompoundSelector::Single { selector: Selector::EqualTo, version }) if version == "*" => {
+                Some(self.package.clone())
+            }
+            Some(version) => Some(format!("{}{}", self.package, version)),
+        }
+    }
+
+    /// Maps the MatchSpec to query parameters for repodata APIs. The package always comes first,
+    /// followed by whichever of the version, build, subdir and channel are set. A channel list is
+    /// joined with `,`.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "conda-forge::numpy>=1.20".parse().unwrap();
+    /// assert_eq!(
+    ///     ms.as_query_params(),
+    ///     vec![
+    ///         ("package".to_string(), "numpy".to_string()),
+    ///         ("version".to_string(), ">=1.20".to_string()),
+    ///         ("channel".to_string(), "conda-forge".to_string()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn as_query_params(&self) -> Vec<(String, String)> {
+        let channel = match &self.channel {
+            Some(_) if !self.channels.is_empty() => Some(self.channels.join(",")),
+            channel => channel.clone(),
+        };
+        [
+            ("package", Some(self.package.clone())),
+            ("version", self.version.as_ref().map(|v| v.to_string())),
+            ("build", self.build.clone()),
+            ("subdir", self.subdir.clone()),
+            ("channel", channel),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect()
+    }
+
+    /// True when both MatchSpecs constrain the same package, but no version could satisfy both of
+    /// them. A MatchSpec without a version never conflicts.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let a: MatchSpec = "numpy>=2.0".parse().unwrap();
+    /// let b: MatchSpec = "numpy<1.0".parse().unwrap();
+    /// assert!(a.conflicts_with(&b));
+    /// ```
+    pub fn conflicts_with(&self, other: &MatchSpec) -> bool {
+        match (&self.version, &other.version) {
+            (Some(a), Some(b)) => self.package == other.package && !a.version_range_overlaps(b),
+            _ => false,
+        }
+    }
+
+    /// Clones the MatchSpec replacing only the version constraint
+    /// ```
+    /// use rust_matchspec::{CompoundSelector, MatchSpec};
+    ///
+    /// let ms: MatchSpec = "conda-forge::numpy>=1.0".parse().unwrap();
+    /// let variant = ms.with_version(CompoundSelector::from(("<", "2.0")));
+    /// assert_eq!(variant.to_string(), "conda-forge::numpy<2.0");
+    /// ```
+    pub fn with_version(&self, cs: CompoundSelector<String>) -> MatchSpec {
+        MatchSpec {
+            version: Some(cs),
+            raw: None,
+            ..self.clone()
+        }
+    }
+
+    /// Clones the MatchSpec replacing only the package name
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "conda-forge::pytorch>=1.10".parse().unwrap();
+    /// assert_eq!(ms.rename_package("torch").to_string(), "conda-forge::torch>=1.10");
+    /// ```
+    pub fn rename_package(&self, new_name: impl Into<String>) -> MatchSpec {
+        MatchSpec {
+            package: new_name.into(),
+            raw: None,
+            ..self.clone()
+        }
+    }
+
+    /// Removes every pair with this key from the key_value_pairs. Fields that were hoisted out of
+    /// that key, like the `build` from `[build=py39*]`, are cleared too. Fields set some other way,
+    /// like the build in `numpy 1.21.0 py39h_0`, are left alone.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let mut ms: MatchSpec = "numpy>=1.20[build=py39*, license=BSD]".parse().unwrap();
+    /// ms.remove_key("build");
+    /// assert_eq!(ms.build, None);
+    /// assert_eq!(ms.to_string(), "numpy>=1.20[license=BSD]");
+    /// ```
+    pub fn remove_key(&mut self, key: &str) {
+        let key = key.to_ascii_lowercase();
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.key_value_pairs)
+            .into_iter()
+            .partition(|(k, _)| *k == key);
+        self.key_value_pairs = kept;
+        if removed.is_empty() {
+            return;
+        }
+        self.raw = None;
+
+        // Only `=` values are hoisted into the string fields
+        let values: Vec<&str> = removed
+            .iter()
+            .filter_map(|(_, cs)| match cs {
+                CompoundSelector::Single { selector: Selector::EqualTo, version } => Some(version.as_str()),
+                _ => None,
+            })
+            .collect();
+        let hoisted = |field: &Option<String>| {
+            field
+                .as_deref()
+                .map(|field| values.iter().any(|v| *v == field || normalize_subdir(v) == field))
+                .unwrap_or(false)
+        };
+
+        match key.as_str() {
+            "build" if hoisted(&self.build) => self.build = None,
+            "channel" if hoisted(&self.channel) => self.channel = None,
+            "subdir" if hoisted(&self.subdir) => self.subdir = None,
+            "namespace" if hoisted(&self.namespace) => self.namespace = None,
+            "exclude" => self.exclude.retain(|glob| !values.contains(&glob.as_str())),
+            // build_number only ever comes from the key_value_pairs
+            "build_number" => self.build_number = None,
+            // The last version key replaced any inline version when parsing
+            "version" if self.version.as_ref() == removed.last().map(|(_, cs)| cs) => self.version = None,
+            _ => (),
+        }
+    }
+
+    /// Returns every `(field_name, selector, version)` constraint in this MatchSpec, walking the
+    /// version, the build_number, and then the rest of the key_value_pairs.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20[build_number=2]".parse().unwrap();
+    /// assert_eq!(
+    ///     ms.constraints(),
+    ///     vec![
+    ///         ("version", &Selector::GreaterThanOrEqualTo, "1.20"),
+    ///         ("build_number", &Selector::EqualTo, "2"),
+    ///     ]
+    /// );
+    /// ```
+    pub fn constraints(&self) -> Vec<(&str, &Selector, &str)> {
+        let mut constraints: Vec<(&str, &Selector, &str)> = Vec::new();
+
+        let fields = [("version", &self.version), ("build_number", &self.build_number)];
+        for (field, compound_selector) in fields {
+            if let Some(cs) = compound_selector {
+                constraints.extend(cs.selectors().into_iter().map(|(s, v)| (field, s, v)));
+            }
+        }
+
+        // build_number has already been hoisted out of the key_value_pairs above
+        for (key, cs) in self.key_value_pairs.iter().filter(|(key, _)| key != "build_number") {
+            constraints.extend(cs.selectors().into_iter().map(|(s, v)| (key.as_str(), s, v)));
+        }
+
+        constraints
+    }
+
+    /// Clones the MatchSpec with redundant clauses removed from the version and build_number, see
+    /// `CompoundSelector::simplify`.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.0,>=1.2".parse().unwrap();
+    /// assert_eq!(ms.simplify().to_string(), "numpy>=1.2");
+    /// ```
+    pub fn simplify(&self) -> MatchSpec {
+        MatchSpec {
+            version: self.version.as_ref().map(CompoundSelector::simplify),
+            build_number: self.build_number.as_ref().map(CompoundSelector::simplify),
+            raw: None,
+            ..self.clone()
+        }
+    }
+
+    /// Like `==` but compares versions as versions instead of as strings, so `numpy==1.0` is
+    /// semantically equal to `numpy==1.0.0`. This also compares the build_number constraint.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let a: MatchSpec = "numpy==1.0".parse().unwrap();
+    /// let b: MatchSpec = "numpy==1.0.0".parse().unwrap();
+    /// assert_ne!(a, b);
+    /// assert!(a.semantically_eq(&b));
+    /// ```
+    pub fn semantically_eq(&self, other: &MatchSpec) -> bool {
+        let selector_eq = |a: &Option<CompoundSelector<String>>, b: &Option<CompoundSelector<String>>| match (a, b) {
+            (Some(a), Some(b)) => a.semantically_eq(b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        self.channel == other.channel
+            && self.channels == other.channels
+            && self.subdir == other.subdir
+            && self.namespace == other.namespace
+            && self.package == other.package
+            && selector_eq(&self.version, &other.version)
+            && self.build == other.build
+            && selector_eq(&self.build_number, &other.build_number)
+            && self.exclude == other.exclude
+    }
+
+    /// The package family, which is the package name up to the first `-`. Variants like
+    /// `tensorflow-gpu` and `tensorflow-cpu` are both in the `tensorflow` family. Note this also
+    /// groups unrelated packages that happen to share a prefix, like `python-dateutil`.
+    pub fn package_family(&self) -> &str {
+        self.package.split('-').next().unwrap_or_default()
+    }
+
+    /// True when both MatchSpecs are for the same package family from the same channel, ignoring
+    /// the version and build. See `package_family`.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let a: MatchSpec = "tensorflow-gpu>=2.0".parse().unwrap();
+    /// let b: MatchSpec = "tensorflow-cpu 2.9.1".parse().unwrap();
+    /// assert!(a.same_family(&b));
+    /// ```
+    pub fn same_family(&self, other: &MatchSpec) -> bool {
+        self.package_family() == other.package_family()
+            && self.channel == other.channel
+            && self.channels == other.channels
+    }
+}
+
+impl MatchSpec {
+    /// Matches a PackageCandidate, or anything else implementing `Candidate`
+    pub fn is_match<C: Candidate + ?Sized>(&self, pc: &C) -> bool {
+        self.is_match_with(pc, &MatchOptions::default())
+    }
+
+    /// Matches a PackageCandidate with the leniency controlled by MatchOptions
+    /// ```
+    /// use rust_matchspec::{MatchOptions, MatchSpec};
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let ms: MatchSpec = "pyyaml>=6".parse().unwrap();
+    /// let pc = PackageCandidate::from(r#"{"name": "PyYAML", "version": "6.0"}"#);
+    /// assert!(!ms.is_match(&pc));
+    ///
+    /// let options = MatchOptions { case_insensitive: true, ..MatchOptions::default() };
+    /// assert!(ms.is_match_with(&pc, &options));
+    /// ```
+    pub fn is_match_with<C: Candidate + ?Sized>(&self, pc: &C, opts: &MatchOptions) -> bool {
+        // The checks go from cheapest to most expensive, so most candidates are ruled out before
+        // the name glob and version comparison
+        self.is_location_match(pc, opts)
+            && self.is_name_match(pc.name(), opts.case_insensitive)
+            && self.is_details_match(pc, opts)
+    }
+
+    /// Matches borrowed candidate fields directly, for callers like Arrow or other columnar data
+    /// that don't want to build a PackageCandidate for every row. The channel, filename and
+    /// timestamp are unknown, so they never fail a match.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20[build_number=0]".parse().unwrap();
+    /// assert!(ms.is_match_fields("numpy", Some("1.26.4"), Some("py311_0"), Some(0), Some("linux-64")));
+    /// assert!(!ms.is_match_fields("numpy", Some("1.19.5"), None, Some(0), None));
+    /// ```
+    pub fn is_match_fields(
+        &self,
+        name: &str,
+        version: Option<&str>,
+        build: Option<&str>,
+        build_number: Option<u32>,
+        subdir: Option<&str>,
+    ) -> bool {
+        self.is_match(&CandidateFields {
+            name,
+            version,
+            build,
+            build_number,
+            subdir,
+        })
+    }
+
+    /// True if a candidate from `candidate_channel` can satisfy this MatchSpec, given the channel
+    /// priority list in use, highest priority first. A channel named in the spec, like
+    /// `conda-forge::numpy` or `conda-forge,defaults::numpy`, is always used over the priority
+    /// list, so only those channels are allowed. Otherwise the channel has to be in the priority
+    /// list, and an empty list allows any channel. Under strict channel priority the first allowed
+    /// channel in the priority list is the one to take candidates from.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let priority = ["conda-forge", "defaults"];
+    /// let ms: MatchSpec = "numpy".parse().unwrap();
+    /// assert!(ms.channel_allowed("defaults", &priority));
+    /// assert!(!ms.channel_allowed("bioconda", &priority));
+    ///
+    /// let ms: MatchSpec = "bioconda::samtools".parse().unwrap();
+    /// assert!(ms.channel_allowed("bioconda", &priority));
+    /// assert!(!ms.channel_allowed("conda-forge", &priority));
+    /// ```
+    pub fn channel_allowed(&self, candidate_channel: &str, priority: &[&str]) -> bool {
+        match &self.channel {
+            Some(channel) if self.channels.is_empty() => channel == candidate_channel,
+            Some(_) => self.channels.iter().any(|channel| channel == candidate_channel),
+            None => priority.is_empty() || priority.contains(&candidate_channel),
+        }
+    }
+
+    /// Everything in `is_match_with` other than the package name
+    pub(crate) fn is_match_except_name<C: Candidate + ?Sized>(&self, pc: &C, opts: &MatchOptions) -> bool {
+        self.is_location_match(pc, opts) && self.is_details_match(pc, opts)
+    }
+
+    /// The channel and subdir, which are plain string comparisons
+    fn is_location_match<C: Candidate + ?Sized>(&self, pc: &C, opts: &MatchOptions) -> bool {
+        // Candidates don't always know where they came from, so only a known channel can fail
+        let channel_matches = opts.ignore_channel
+            || match (&self.channel, pc.channel()) {
+                (Some(a), Some(b)) if self.channels.is_empty() => a == b,
+                (Some(_), Some(b)) => self.channels.iter().any(|channel| channel == b),
+                _ => true,
+            };
+
+        channel_matches && (self.subdir.is_none() || self.subdir.as_deref() == pc.subdir())
+    }
+
+    /// Everything other than the name and location, leaving the version comparison until last
+    fn is_details_match<C: Candidate + ?Sized>(&self, pc: &C, opts: &MatchOptions) -> bool {
+        let version = pc.version().unwrap_or_default();
+        let prerelease_allowed = || {
+            opts.allow_prerelease
+                || !is_prerelease(version)
+                || self
+                    .version
+                    .as_ref()
+                    .map(|v| v.selectors().iter().any(|(_, v)| is_prerelease(v)))
+                    .unwrap_or(false)
+        };
+
+        self.is_build_number_match(&pc.build_number())
+            && (opts.ignore_build || self.is_build_match(pc.build()))
+            && self.is_extension_match(pc.filename())
+            && self.is_timestamp_match(pc.timestamp())
+            && self.is_platform_match(pc)
+            && self.is_version_match(version)
+            && prerelease_allowed()
+    }
+
+    /// Returns the matching candidate with the highest version, using the highest build_number to
+    /// break ties. Candidates without a version or build_number sort below ones that have them.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let ms: MatchSpec = "numpy<2".parse().unwrap();
+    /// let candidates = vec![
+    ///     PackageCandidate::from(r#"{"name": "numpy", "version": "1.21.0"}"#),
+    ///     PackageCandidate::from(r#"{"name": "numpy", "version": "1.26.4"}"#),
+    ///     PackageCandidate::from(r#"{"name": "numpy", "version": "2.0.0"}"#),
+    /// ];
+    /// assert_eq!(ms.best_match(&candidates).unwrap().version.as_deref(), Some("1.26.4"));
+    /// ```
+    pub fn best_match<'a>(&self, candidates: &'a [PackageCandidate]) -> Option<&'a PackageCandidate> {
+        candidates
+            .iter()
+            .filter(|pc| self.is_match(pc))
+            .max_by(|a, b| {
+                let version = match (&a.version, &b.version) {
+                    (Some(a), Some(b)) => compare_versions(a, b),
+                    (a, b) => a.is_some().cmp(&b.is_some()),
+                };
+                version.then(a.build_number.cmp(&b.build_number))
+            })
+    }
+
+    /// Finds the matching candidates in an index of candidates keyed by package name. Unless the
+    /// package is a glob only the bucket for the spec's package is scanned, which makes repeated
+    /// lookups against a large index cheap. The buckets are trusted to hold only candidates with
+    /// that name.
+    /// ```
+    /// use std::collections::HashMap;
+    /// use rust_matchspec::MatchSpec;
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let mut index: HashMap<String, Vec<PackageCandidate>> = HashMap::new();
+    /// index.entry("zlib".to_string()).or_default().push(PackageCandidate::from(r#"{"name": "zlib", "version": "1.2.13"}"#));
+    /// index.entry("numpy".to_string()).or_default().push(PackageCandidate::from(r#"{"name": "numpy", "version": "1.26.4"}"#));
+    ///
+    /// let ms: MatchSpec = "zlib>=1.2".parse().unwrap();
+    /// assert_eq!(ms.filter_index(&index).len(), 1);
+    /// ```
+    pub fn filter_index<'a>(&self, index: &'a HashMap<String, Vec<PackageCandidate>>) -> Vec<&'a PackageCandidate> {
+        if self.package_has_glob() {
+            index.values().flatten().filter(|pc| self.is_match(pc)).collect()
+        } else {
+            index
+                .get(&self.package)
+                .into_iter()
+                .flatten()
+                .filter(|pc| self.is_match(pc))
+                .collect()
+        }
+    }
+
+    /// Finds the candidates that depend on this spec, meaning they have a `depends` entry for the
+    /// spec's package that can be satisfied by the same versions. See
+    /// `PackageCandidate::depends_match`.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let ms: MatchSpec = "python>=3.8".parse().unwrap();
+    /// let candidates = vec![
+    ///     PackageCandidate::from(r#"{"name": "six", "depends": ["python"]}"#),
+    ///     PackageCandidate::from(r#"{"name": "zlib", "depends": ["libgcc-ng"]}"#),
+    /// ];
+    /// assert_eq!(ms.dependents(&candidates)[0].name, "six");
+    /// ```
+    pub fn dependents<'a>(&self, candidates: &'a [PackageCandidate]) -> Vec<&'a PackageCandidate> {
+        candidates.iter().filter(|pc| pc.depends_match(self)).collect()
+    }
+
+    /// Returns the distinct subdirs of the matching candidates, in sorted order. Matching
+    /// candidates without a subdir are left out.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+    /// let candidates = vec![
+    ///     PackageCandidate::from(r#"{"name": "numpy", "version": "1.26.4", "subdir": "osx-arm64"}"#),
+    ///     PackageCandidate::from(r#"{"name": "numpy", "version": "1.26.4", "subdir": "linux-64"}"#),
+    /// ];
+    /// assert_eq!(ms.matching_subdirs(&candidates).into_iter().collect::<Vec<_>>(), vec!["linux-64", "osx-arm64"]);
+    /// ```
+    pub fn matching_subdirs(&self, candidates: &[PackageCandidate]) -> BTreeSet<String> {
+        candidates
+            .iter()
+            .filter(|pc| self.is_match(*pc))
+            .filter_map(|pc| pc.subdir.clone())
+            .collect()
+    }
+
+    /// Matches a package filename like `numpy-1.21.0-py39h_0.tar.bz2`, using the name, version
+    /// and build from the filename. Filenames that can't be split up never match, see
+    /// `PackageCandidate::from_filename`. Filenames don't carry a build_number, so specs with a
+    /// build_number constraint don't match either.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "numpy>=1.20,<2".parse().unwrap();
+    /// assert!(ms.matches_filename("numpy-1.21.0-py39h_0.tar.bz2"));
+    /// ```
+    pub fn matches_filename(&self, filename: &str) -> bool {
+        PackageCandidate::from_filename(filename)
+            .map(|pc| self.is_match(&pc))
+            .unwrap_or(false)
+    }
+
+    /// Matches the package format given by `[extension=conda]` or `[extension=tar.bz2]` against
+    /// the suffix of a package filename. Always true if the spec has no extension key, and always
+    /// false if it does but there is no filename to check. Formats have no order, so a selector
+    /// like `>=conda` never matches.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "numpy[extension=conda]".parse().unwrap();
+    /// assert!(ms.is_extension_match(Some("numpy-1.21.0-py39h_0.conda")));
+    /// assert!(!ms.is_extension_match(Some("numpy-1.21.0-py39h_0.tar.bz2")));
+    /// ```
+    pub fn is_extension_match(&self, filename: Option<&str>) -> bool {
+        self.key_value_pairs
+            .iter()
+            .filter(|(key, _)| key == "extension")
+            .all(|(_, cs)| {
+                let has_extension = |extension: &str| {
+                    filename
+                        .map(|f| f.ends_with(&format!(".{}", extension.trim_start_matches('.'))))
+                        .unwrap_or(false)
+                };
+                match cs {
+                    CompoundSelector::Single { selector: Selector::EqualTo | Selector::ExactlyEqualTo, version } => {
+                        has_extension(version)
+                    }
+                    CompoundSelector::Single { selector: Selector::NotEqualTo, version } => {
+                        filename.is_some() && !has_extension(version)
+                    }
+                    _ => false,
+                }
+            })
+    }
+
+    /// Matches the build string, which may be a glob like `py3*`. Builds without a `*`, like the
+    /// hash style `h5eee18b_0`, have to match exactly. Always true if the spec has no build. A
+    /// candidate without a build is matched as an empty build, so only a glob that can match
+    /// an empty string, like `*`, accepts it.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "python 3.10 py3*".parse().unwrap();
+    /// assert!(ms.is_build_match(Some("py310h_0")));
+    /// assert!(!ms.is_build_match(Some("h12debd9_0")));
+    /// assert!(!ms.is_build_match(None));
+    /// assert!("python[build=*]".parse::<MatchSpec>().unwrap().is_build_match(None));
+    /// ```
+    pub fn is_build_match(&self, build: Option<&str>) -> bool {
+        match (&self.build, build) {
+            (None, _) => true,
+            (Some(exact), Some(build)) if !has_glob(exact) => exact == build,
+            (Some(glob), Some(build)) => is_match_glob_str(glob, build),
+            (Some(glob), None) => has_glob(glob) && is_match_glob_str(glob, ""),
+        }
+    }
+
+    /// Matches the `[platform=...]` and `[arch=...]` keys against the platform and architecture
+    /// derived from the candidate's subdir, see `Candidate::platform`. Both can be globs and can
+    /// use `!=`. A candidate without a platform never matches these keys.
+    pub fn is_platform_match<C: Candidate + ?Sized>(&self, pc: &C) -> bool {
+        self.key_value_pairs.iter().all(|(key, cs)| {
+            let value = match key.as_str() {
+                "platform" => pc.platform(),
+                "arch" => pc.arch(),
+                _ => return true,
+            };
+            match (cs, value) {
+                (CompoundSelector::Single { selector: Selector::NotEqualTo, version }, Some(value)) => {
+                    !is_match_glob_str(version, value)
+                }
+                (CompoundSelector::Single { selector: _, version }, Some(value)) => is_match_glob_str(version, value),
+                _ => false,
+            }
+        })
+    }
+
+    /// Matches a `noarch: python` candidate. These packages are built once and installed under
+    /// any python version, so the subdir constraint doesn't apply to them. Returns false for any
+    /// candidate that isn't `noarch: python`.
+    pub fn satisfied_by_noarch(&self, pc: &PackageCandidate) -> bool {
+        pc.is_noarch_python()
+            && self.is_package_version_match(&pc.name, pc.version.as_ref().unwrap_or(&String::new()))
+            && self.is_build_number_match(&pc.build_number)
+            && self.is_build_match(pc.build.as_deref())
+    }
+
+    /// Matches the `[timestamp>=...]` keys against a candidate's epoch timestamp. The spec can
+    /// give either an epoch timestamp or an ISO/RFC3339 date. Always true if the spec has no
+    /// timestamp key, and always false if it does but the candidate has no timestamp.
+    /// ```
+    /// use rust_matchspec::matchspec::*;
+    ///
+    /// let ms: MatchSpec = "numpy[timestamp>=2021-01-01]".parse().unwrap();
+    /// assert!(ms.is_timestamp_match(Some(1640995200000)));
+    /// assert!(!ms.is_timestamp_match(Some(1577836800000)));
+    /// ```
+    pub fn is_timestamp_match(&self, timestamp: Option<u64>) -> bool {
+        self.key_value_pairs
+            .iter()
+            .filter(|(key, _)| key == "timestamp")
+            .all(|(_, cs)| {
+                let timestamp = match timestamp {
+                    Some(timestamp) => normalize_timestamp_millis(timestamp),
+                    None => return false,
+                };
+                let is_match = |(selector, value): &(&Selector, &str)| {
+                    parse_timestamp_millis(value)
+                        .map(|value| selector.is_ordering_match(timestamp.cmp(&value)))
+                        .unwrap_or(false)
+                };
+                match cs {
+                    CompoundSelector::Or { .. } => cs.selectors().iter().any(is_match),
+                    _ => cs.selectors().iter().all(is_match),
+                }
+            })
+    }
+
+    /// Checks the build_number constraint, if there is one. A candidate without a build_number
+    /// can't satisfy a constraint on it, so it only matches specs that don't have one.
+    pub fn is_build_number_match(&self, build_number: &Option<u32>) -> bool {
+        match (&self.build_number, build_number) {
+            (Some(cs), Some(number)) => cs.is_match(&number.to_string()),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+    }
+
+    /// Checks both the build string and the build_number, see `is_build_match` and
+    /// `is_build_number_match`. This is named apart from `is_build_match` because that only takes
+    /// the build string.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let ms: MatchSpec = "python[build=py3*,build_number='>=2']".parse().unwrap();
+    /// assert!(ms.is_build_and_number_match(Some("py310h_2"), &Some(2)));
+    /// assert!(!ms.is_build_and_number_match(Some("py310h_1"), &Some(1)));
+    /// ```
+    pub fn is_build_and_number_match(&self, build: Option<&str>, build_number: &Option<u32>) -> bool {
+        self.is_build_match(build) && self.is_build_number_match(build_number)
+    }
+}
+
+/// Checks that every MatchSpec is satisfied by at least one PackageCandidate in the environment.
+/// This is useful for validating that a lockfile satisfies a set of requirements. On failure the
+/// specs that were not met are returned.
+/// ```
+/// use rust_matchspec::{environment_satisfies, MatchSpec};
+/// use rust_matchspec::package_candidate::PackageCandidate;
+///
+/// let specs: Vec<MatchSpec> = vec!["python>=3.9".parse().unwrap()];
+/// let env = vec![PackageCandidate::from(r#"{"name": "python", "version": "3.10.4"}"#)];
+/// assert!(environment_satisfies(&specs, &env).is_ok());
+/// ```
+pub fn environment_satisfies(
+    specs: &[MatchSpec],
+    env: &[PackageCandidate],
+) -> Result<(), Vec<MatchSpec>> {
+    let unmet: Vec<MatchSpec> = specs
+        .iter()
+        .filter(|ms| !env.iter().any(|pc| ms.is_match(pc)))
+        .cloned()
+        .collect();
+
+    if unmet.is_empty() {
+        Ok(())
+    } else {
+        Err(unmet)
+    }
+}
+
+/// Groups the candidates by package name, keeping only the ones that match at least one of the
+/// MatchSpecs. Each candidate appears once even if several specs match it, and names without any
+/// matching candidates are left out.
+/// ```
+/// use rust_matchspec::{group_and_filter, MatchSpec};
+/// use rust_matchspec::package_candidate::PackageCandidate;
+///
+/// let specs: Vec<MatchSpec> = vec!["python>=3.9".parse().unwrap()];
+/// let candidates = vec![
+///     PackageCandidate::from(r#"{"name": "python", "version": "3.10.4"}"#),
+///     PackageCandidate::from(r#"{"name": "python", "version": "3.8.16"}"#),
+/// ];
+/// assert_eq!(group_and_filter(&candidates, &specs)["python"].len(), 1);
+/// ```
+pub fn group_and_filter(
+    candidates: &[PackageCandidate],
+    specs: &[MatchSpec],
+) -> HashMap<String, Vec<PackageCandidate>> {
+    let mut groups: HashMap<String, Vec<PackageCandidate>> = HashMap::new();
+    for pc in candidates.iter().filter(|pc| specs.iter().any(|ms| ms.is_match(pc))) {
+        groups.entry(pc.name.clone()).or_default().push(pc.clone());
+    }
+    groups
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod matching {
+        use crate::matchspec::*;
+
+        #[test]
+        fn package_only() {
+            let mut ms: MatchSpec = "tensorflow".parse().unwrap();
+
+            assert!(ms.is_package_match("tensorflow".to_string()));
+            assert!(!ms.is_package_match("pytorch".to_string()));
+
+            ms = "tensor*-gpu".parse().unwrap();
+            assert!(ms.is_package_match("tensorflow-gpu".to_string()));
+            assert!(!ms.is_package_match("tennnnsorflow-gpu".to_string()));
+
+            ms = "tensorflow*".parse().unwrap();
+            assert!(ms.is_package_match("tensorflow".to_string()));
+            assert!(ms.is_package_match("tensorflow-gpu".to_string()));
+
+            ms = "*-gpu".parse().unwrap();
+            assert!(ms.is_package_match("tensorflow-gpu".to_string()));
+            assert!(!ms.is_package_match("tensorflow".to_string()));
+
+            // Illegal chars
+            assert!(!ms.is_package_match("python>3.10[name=* vmd5=\"abcdef1312\"]".to_string()));
+        }
+
+        #[test]
+        fn glob_strings() {
+            assert!(is_match_glob_str("pytorch", "pytorch"));
+            assert!(!is_match_glob_str("torch", "pytorch"));
+            assert!(!is_match_glob_str("py", "pytorch"));
+
+            assert!(is_match_glob_str("*", "pytorch"));
+            assert!(is_match_glob_str("*", ""));
+            assert!(is_match_glob_str("py*", "pytorch"));
+            assert!(!is_match_glob_str("py*", "numpy"));
+            assert!(is_match_glob_str("*torch", "pytorch"));
+            assert!(!is_match_glob_str("*torch", "torchvision"));
+
+            // Globs in the middle
+            assert!(is_match_glob_str("py*torch", "pytorch"));
+            assert!(is_match_glob_str("py*torch", "pyXXXtorch"));
+            assert!(!is_match_glob_str("py*torch", "pytorchvision"));
+            assert!(!is_match_glob_str("py*torch", "numpytorch"));
+            assert!(is_match_glob_str("a*b*c", "aXbYc"));
+            assert!(is_match_glob_str("a*b*c", "abc"));
+            assert!(!is_match_glob_str("a*b*c", "acb"));
+
+            // Parts can't overlap each other
+            assert!(!is_match_glob_str("a*a", "a"));
+            assert!(is_match_glob_str("a*a", "aa"));
+            assert!(!is_match_glob_str("ab*bc", "abc"));
+        }
+
+        #[test]
+        fn package_middle_glob() {
+            let ms: MatchSpec = "py*torch".parse().unwrap();
+            assert!(ms.is_package_match("pytorch".to_string()));
+            assert!(ms.is_package_match("pyXXXtorch".to_string()));
+            assert!(!ms.is_package_match("pytorch-cpu".to_string()));
+            assert!(!ms.is_package_match("torch".to_string()));
+
+            let ms: MatchSpec = "py*torch>=1.10".parse().unwrap();
+            assert!(ms.is_package_version_match("pyXXXtorch", "1.12"));
+            assert!(!ms.is_package_version_match("pyXXXtorch", "1.9"));
+        }
+
+        #[test]
+        fn package_with_exclude() {
+            let ms: MatchSpec = "tensorflow*[exclude=tensorflow-gpu]".parse().unwrap();
+            assert_eq!(ms.exclude, vec!["tensorflow-gpu".to_string()]);
+            assert!(ms.is_package_match("tensorflow".to_string()));
+            assert!(ms.is_package_match("tensorflow-cpu".to_string()));
+            assert!(!ms.is_package_match("tensorflow-gpu".to_string()));
+
+            // Excludes can be globs and can be repeated
+            let ms: MatchSpec = "tensorflow*>=2.0[exclude='*-gpu', exclude=tensorflow-base]"
+                .parse()
+                .unwrap();
+            assert!(ms.is_package_version_match("tensorflow-cpu", "2.9.1"));
+            assert!(!ms.is_package_version_match("tensorflow-gpu", "2.9.1"));
+            assert!(!ms.is_package_version_match("tensorflow-base", "2.9.1"));
+        }
+
+        #[test]
+        fn literal_package() {
+            let glob: MatchSpec = "foo*bar".parse().unwrap();
+            let literal = MatchSpec::parse_with(
+                "foo*bar",
+                &ParseOptions { literal_package: true, ..ParseOptions::default() },
+            )
+            .unwrap();
+            assert!(literal.literal_package);
+            assert_ne!(glob, literal);
+
+            // The glob matches anything in the middle, but not a literal star
+            assert!(glob.is_package_match("foo-baz-bar".to_string()));
+            assert!(!glob.is_package_match("foo*bar".to_string()));
+
+            // The literal name only matches itself
+            assert!(literal.is_package_match("foo*bar".to_string()));
+            assert!(!literal.is_package_match("foo-baz-bar".to_string()));
+            assert!(literal.is_package_version_match("foo*bar", "1.0"));
+            assert!(!literal.is_package_version_match("foobar", "1.0"));
+        }
+
+        #[test]
+        fn package_glob() {
+            let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+            assert!(!ms.package_has_glob());
+            assert_eq!(ms.package_pattern(), "numpy");
+
+            let ms: MatchSpec = "conda-forge::py*-cpu".parse().unwrap();
+            assert!(ms.package_has_glob());
+            assert_eq!(ms.package_pattern(), "py*-cpu");
+
+            // The default MatchSpec is the `*` glob
+            assert!(MatchSpec::default().package_has_glob());
+
+            // Literal packages are never globs, even with a star in them
+            let options = ParseOptions { literal_package: true, ..ParseOptions::default() };
+            let ms = MatchSpec::parse_with("foo*bar", &options).unwrap();
+            assert!(!ms.package_has_glob());
+            assert_eq!(ms.package_pattern(), "foo*bar");
+        }
+
+        #[test]
+        fn package_and_version_only() {
+            let ms: MatchSpec = "tensorflow>1.9.2".parse().unwrap();
+
+            assert!(ms.is_package_version_match("tensorflow", "1.9.3"));
+            assert!(!ms.is_package_version_match("tensorflow", "1.9.0"));
+        }
+
+        #[test]
+        fn compound_selectors() {
+            let single = CompoundSelector::Single {
+                selector: Selector::GreaterThan,
+                version: "1.1.1",
+            };
+
+            assert!(single.is_match("1.2.1"));
+            assert!(single.is_match("3.0.0"));
+            assert!(!single.is_match("1.1.1"));
+            assert!(!single.is_match("0.1.1"));
+
+            let and = CompoundSelector::And {
+                first_selector: Selector::GreaterThan,
+                first_version: "1.1.1",
+                second_selector: Selector::LessThanOrEqualTo,
+                second_version: "1.2.1",
+            };
+
+            assert!(and.is_match("1.2.1"));
+            assert!(and.is_match("1.1.7"));
+            assert!(!and.is_match("1.2.2"));
+            assert!(!and.is_match("0.1.1"));
+
+            let or = CompoundSelector::Or {
+                first_selector: Selector::LessThan,
+                first_version: "1.1.1",
+                second_selector: Selector::GreaterThan,
+                second_version: "1.2.1",
+            };
+
+            assert!(or.is_match("3.0.0"));
+            assert!(or.is_match("0.1.1"));
+            assert!(!or.is_match("1.2.1"));
+            assert!(!or.is_match("1.1.1"));
+            assert!(!or.is_match("1.1.7"));
+        }
+
+        #[test]
+        fn key_values_map() {
+            let ms: MatchSpec = "python>3.6[subdir=linux-64, build_number='>=2', license=MIT, license=GPL]"
+                .parse()
+                .unwrap();
+            let kv = ms.key_values();
+
+            assert_eq!(kv.len(), 3);
+            assert_eq!(kv.get("subdir"), Some(&&CompoundSelector::from(("=", "linux-64"))));
+            assert_eq!(kv.get("build_number"), Some(&&CompoundSelector::from((">=", "2"))));
+            // Duplicate keys keep the last value
+            assert_eq!(kv.get("license"), Some(&&CompoundSelector::from(("=", "GPL"))));
+            assert_eq!(kv.get("md5"), None);
+        }
+
+        #[test]
+        fn environment_markers() {
+            let ms: MatchSpec = "pkg>=1.0[python_version>=3.8, sys_platform=linux, build=py*]".parse().unwrap();
+            assert_eq!(
+                ms.markers(),
+                vec![
+                    ("python_version", &CompoundSelector::from((">=", "3.8"))),
+                    ("sys_platform", &CompoundSelector::from(("=", "linux"))),
+                ]
+            );
+            assert!(ms.markers()[0].1.is_match("3.11"));
+            assert!(!ms.markers()[0].1.is_match("3.7"));
+
+            // Markers don't get in the way of matching
+            assert!(ms.is_package_version_match("pkg", "1.2"));
+
+            let ms: MatchSpec = "pkg[python_version='>=3.8,<4']".parse().unwrap();
+            assert_eq!(ms.markers(), vec![("python_version", &CompoundSelector::from((">=", "3.8", ",", "<", "4")))]);
+            assert_eq!(ms.to_string(), "pkg[python_version='>=3.8,<4']");
+        }
+
+        #[test]
+        fn version_specificity() {
+            let specificity = |s: &str| s.parse::<MatchSpec>().unwrap().version_specificity();
+            assert_eq!(specificity("numpy 1"), 1);
+            assert_eq!(specificity("numpy 1.2"), 2);
+            assert_eq!(specificity("numpy 1.2.3"), 3);
+            assert_eq!(specificity("numpy >=1,<1.2.3"), 3);
+            assert_eq!(specificity("numpy 1.2.*"), 2);
+            assert_eq!(specificity("numpy 1!1.2+local.3"), 2);
+            assert_eq!(specificity("numpy"), 0);
+        }
+
+        #[test]
+        fn duplicate_keys() {
+            let ms: MatchSpec = "python[subdir=linux-64, license=MIT, subdir=osx-64, license=GPL, subdir=win-64]"
+                .parse()
+                .unwrap();
+            assert_eq!(ms.duplicate_keys(), vec!["subdir", "license"]);
+
+            let ms: MatchSpec = "python[subdir=linux-64, license=MIT]".parse().unwrap();
+            assert!(ms.duplicate_keys().is_empty());
+            assert!("python".parse::<MatchSpec>().unwrap().duplicate_keys().is_empty());
+        }
+
+        #[test]
+        fn all_constraints() {
+            let ms: MatchSpec = "numpy>=1.20,<2.0[build_number='>=3', subdir=linux-64]".parse().unwrap();
+            assert_eq!(
+                ms.constraints(),
+                vec![
+                    ("version", &Selector::GreaterThanOrEqualTo, "1.20"),
+                    ("version", &Selector::LessThan, "2.0"),
+                    ("build_number", &Selector::GreaterThanOrEqualTo, "3"),
+                    ("subdir", &Selector::EqualTo, "linux-64"),
+                ]
+            );
+
+            let ms: MatchSpec = "numpy".parse().unwrap();
+            assert!(ms.constraints().is_empty());
+        }
+
+        #[test]
+        fn conflicting_specs() {
+            let conflicts = |a: &str, b: &str| {
+                let a: MatchSpec = a.parse().unwrap();
+                let b: MatchSpec = b.parse().unwrap();
+                assert_eq!(a.conflicts_with(&b), b.conflicts_with(&a));
+                a.conflicts_with(&b)
+            };
+
+            assert!(conflicts("numpy>=2.0", "numpy<1.0"));
+            assert!(conflicts("numpy==1.20", "numpy==1.21"));
+            assert!(conflicts("numpy>=1.0,<1.5", "numpy>=1.5,<2.0"));
+
+            assert!(!conflicts("numpy>=1.0", "numpy<2.0"));
+            assert!(!conflicts("numpy>=1.0,<=1.5", "numpy>=1.5,<2.0"));
+            assert!(!conflicts("numpy<1.0|>2.0", "numpy>=2.5"));
+            // Different packages and unversioned specs never conflict
+            assert!(!conflicts("numpy>=2.0", "scipy<1.0"));
+            assert!(!conflicts("numpy>=2.0", "numpy"));
+        }
+
+        #[test]
+        fn swap_version() {
+            let ms: MatchSpec = "main/linux-64::numpy>=1.0[build_number=2]".parse().unwrap();
+            let variant = ms.with_version(CompoundSelector::from(("<", "2.0")));
+
+            assert_eq!(variant.version, Some(CompoundSelector::from(("<", "2.0"))));
+            assert_eq!(variant.channel, ms.channel);
+            assert_eq!(variant.subdir, ms.subdir);
+            assert_eq!(variant.build_number, ms.build_number);
+
+            assert!(ms.is_package_version_match("numpy", "2.5"));
+            assert!(!variant.is_package_version_match("numpy", "2.5"));
+            assert!(!ms.is_package_version_match("numpy", "0.9"));
+            assert!(variant.is_package_version_match("numpy", "0.9"));
+        }
+
+        #[test]
+        fn rename() {
+            let ms: MatchSpec = "pytorch>=1.10[subdir=linux-64]".parse().unwrap();
+            let renamed = ms.rename_package("pytorch-cpu");
+
+            assert_eq!(renamed.package, "pytorch-cpu");
+            assert_eq!(renamed.version, ms.version);
+            assert_eq!(renamed.subdir, ms.subdir);
+
+            assert!(renamed.is_package_version_match("pytorch-cpu", "1.12"));
+            assert!(!renamed.is_package_version_match("pytorch", "1.12"));
+            assert!(!renamed.is_package_version_match("pytorch-cpu", "1.9"));
+            assert!(ms.is_package_version_match("pytorch", "1.12"));
+            assert!(!ms.is_package_version_match("pytorch-cpu", "1.12"));
+        }
+
+        #[test]
+        fn remove_key() {
+            let mut ms: MatchSpec = "numpy>=1.20[build=py39*, subdir=linux-64, Build_Number='>=2', exclude=numpy-base]"
+                .parse()
+                .unwrap();
+            ms.remove_key("build");
+            assert_eq!(ms.build, None);
+            assert!(!ms.key_values().contains_key("build"));
+            assert_eq!(ms.key_value_pairs.len(), 3);
+            assert_eq!(ms.subdir, Some("linux-64".to_string()));
+            assert_eq!(ms.raw(), None);
+
+            // Keys are case insensitive, like when parsing
+            ms.remove_key("BUILD_NUMBER");
+            assert_eq!(ms.build_number, None);
+            ms.remove_key("exclude");
+            assert!(ms.exclude.is_empty());
+            assert_eq!(ms.to_string(), "numpy>=1.20[subdir=linux-64]");
+
+            // Removing a key that isn't there changes nothing
+            let before = ms.clone();
+            ms.remove_key("license");
+            assert_eq!(ms, before);
+
+            // A build that didn't come from a key stays
+            let mut ms: MatchSpec = "numpy 1.21.0 py39h_0".parse().unwrap();
+            ms.remove_key("build");
+            assert_eq!(ms.build, Some("py39h_0".to_string()));
+
+            let mut ms: MatchSpec = "numpy[version='>=1.0,<2.0']".parse().unwrap();
+            ms.remove_key("version");
+            assert_eq!(ms.version, None);
+            assert!(ms.key_value_pairs.is_empty());
+        }
+
+        #[test]
+        fn channel_priority() {
+            let priority = ["conda-forge", "defaults"];
+
+            // Without a channel in the spec any channel in the priority list will do
+            let ms: MatchSpec = "numpy".parse().unwrap();
+            assert!(ms.channel_allowed("conda-forge", &priority));
+            assert!(ms.channel_allowed("defaults", &priority));
+            assert!(!ms.channel_allowed("bioconda", &priority));
+            assert!(ms.channel_allowed("bioconda", &[]));
+
+            // A specific channel wins over the priority list
+            let ms: MatchSpec = "defaults::numpy".parse().unwrap();
+            assert!(ms.channel_allowed("defaults", &priority));
+            assert!(!ms.channel_allowed("conda-forge", &priority));
+            let ms: MatchSpec = "bioconda::samtools".parse().unwrap();
+            assert!(ms.channel_allowed("bioconda", &priority));
+
+            // The first allowed channel in priority order is the one strict priority picks
+            let first_allowed = |ms: &MatchSpec| priority.iter().copied().find(|c| ms.channel_allowed(c, &priority));
+            assert_eq!(first_allowed(&"numpy".parse().unwrap()), Some("conda-forge"));
+            assert_eq!(first_allowed(&"defaults,conda-forge::numpy".parse().unwrap()), Some("conda-forge"));
+            assert_eq!(first_allowed(&"defaults::numpy".parse().unwrap()), Some("defaults"));
+            assert_eq!(first_allowed(&"bioconda::samtools".parse().unwrap()), None);
+        }
+
+        #[test]
+        fn not_equal_prefix() {
+            let candidate = |version: &str| test_candidate("numpy", version);
+
+            for spec in ["numpy !=1.4.*", "numpy[version='!=1.4.*']", "numpy >=1.0,!=1.4.*"] {
+                let ms: MatchSpec = spec.parse().unwrap();
+                assert!(!ms.is_match(&candidate("1.4.7")), "{}", spec);
+                assert!(!ms.is_match(&candidate("1.4")), "{}", spec);
+                assert!(!ms.is_match(&candidate("1.4.0a1")), "{}", spec);
+                assert!(ms.is_match(&candidate("1.5.0")), "{}", spec);
+                assert!(ms.is_match(&candidate("1.5a1")), "{}", spec);
+                assert!(ms.is_match(&candidate("1.40")), "{}", spec);
+                assert!(ms.is_match(&candidate("1.3.9")), "{}", spec);
+            }
+
+            let ms: MatchSpec = "numpy !=1.4.*".parse().unwrap();
+            assert!(ms.conflicts_with(&"numpy 1.4.7".parse().unwrap()));
+            assert!(ms.conflicts_with(&"numpy >=1.4.2,<1.4.8".parse().unwrap()));
+            assert!(!ms.conflicts_with(&"numpy >=1.4,<1.6".parse().unwrap()));
+            assert!(!ms.conflicts_with(&"numpy 1.5.0".parse().unwrap()));
+            // 1.5a1 sorts before 1.5 and isn't in the 1.4 series
+            assert!(!ms.conflicts_with(&"numpy 1.5a1".parse().unwrap()));
+            assert!(!ms.conflicts_with(&"numpy >=1.4,<1.5".parse().unwrap()));
+        }
+
+        #[test]
+        fn from_name_and_selector() {
+            let cs = CompoundSelector::from((">=", "1.20", ",", "<", "2"));
+            let ms = MatchSpec::from(("numpy", cs.clone()));
+            assert_eq!(ms.package, "numpy");
+            assert_eq!(ms.version, Some(cs));
+            assert_eq!(ms.channel, None);
+            assert_eq!(ms.build, None);
+            assert!(ms.key_value_pairs.is_empty());
+
+            let candidate = |version: &str| test_candidate("numpy", version);
+            assert!(ms.is_match(&candidate("1.26.4")));
+            assert!(!ms.is_match(&candidate("2.0.0")));
+            assert!(!ms.is_match(&candidate("1.19.5")));
+        }
+
+        #[test]
+        fn field_columns() {
+            let names = ["numpy", "numpy", "numpy", "scipy"];
+            let versions = [Some("1.26.4"), Some("1.19.5"), Some("1.26.4"), Some("1.26.4")];
+            let builds = [Some("py311_0"), Some("py311_0"), Some("py39_0"), Some("py311_0")];
+            let build_numbers = [Some(0), Some(0), None, Some(0)];
+            let subdirs = [Some("linux-64"), Some("linux-64"), Some("linux-64"), None];
+
+            let ms: MatchSpec = "numpy>=1.20[build=py311*, subdir=linux-64]".parse().unwrap();
+            let mask: Vec<bool> = (0..names.len())
+                .map(|i| ms.is_match_fields(names[i], versions[i], builds[i], build_numbers[i], subdirs[i]))
+                .collect();
+            assert_eq!(mask, vec![true, false, false, false]);
+
+            let ms: MatchSpec = "*[build_number=0]".parse().unwrap();
+            assert!(ms.is_match_fields("scipy", None, None, Some(0), None));
+            assert!(!ms.is_match_fields("scipy", None, None, None, None));
+        }
+
+        #[test]
+        fn hash_builds() {
+            let candidate = |build: &str| PackageCandidate {
+                build: Some(build.to_string()),
+                ..test_candidate("zlib", "1.2.13")
+            };
+
+            for spec in ["zlib[build=h5eee18b_0]", "zlib 1.2.13 h5eee18b_0", "zlib=1.2.13=h5eee18b_0"] {
+                let ms: MatchSpec = spec.parse().unwrap();
+                assert_eq!(ms.build, Some("h5eee18b_0".to_string()), "{}", spec);
+                assert!(ms.is_match(&candidate("h5eee18b_0")), "{}", spec);
+                assert!(!ms.is_match(&candidate("h5eee18b_01")), "{}", spec);
+                assert!(!ms.is_match(&candidate("h5eee18b")), "{}", spec);
+                assert!(!ms.is_match(&candidate("0h5eee18b_0")), "{}", spec);
+            }
+
+            // All hex builds can start with a digit
+            let ms: MatchSpec = "zlib 1.2.13 0a1b2c3d_0".parse().unwrap();
+            assert_eq!(ms.build, Some("0a1b2c3d_0".to_string()));
+            assert!(ms.is_match(&candidate("0a1b2c3d_0")));
+            assert!(!ms.is_match(&candidate("0a1b2c3d_1")));
+        }
+
+        #[cfg(feature = "shell-glob")]
+        #[test]
+        fn shell_glob_classes() {
+            let candidate = |name: &str, build: &str| PackageCandidate {
+                build: Some(build.to_string()),
+                ..test_candidate(name, "1.0")
+            };
+
+            let ms: MatchSpec = "foo[build='py3[0-9]_*']".parse().unwrap();
+            assert!(ms.is_match(&candidate("foo", "py39_0")));
+            assert!(!ms.is_match(&candidate("foo", "py3a_0")));
+            assert!(!ms.is_match(&candidate("foo", "py310_0")));
+
+            let ms: MatchSpec = "foo[build='py3?_0']".parse().unwrap();
+            assert!(ms.is_match(&candidate("foo", "py39_0")));
+            assert!(!ms.is_match(&candidate("foo", "py310_0")));
+
+            // Package names can't contain classes when parsed, only when set directly
+            assert!("lib[a-c]*".parse::<MatchSpec>().map(|ms| ms.package != "lib[a-c]*").unwrap_or(true));
+            let mut ms: MatchSpec = "foo".parse().unwrap();
+            ms.package = "lib[a-c]*".to_string();
+            assert!(ms.package_has_glob());
+            assert!(ms.is_match(&candidate("libabc", "0")));
+            assert!(!ms.is_match(&candidate("libxyz", "0")));
+        }
+
+        #[test]
+        fn build_glob() {
+            let candidate = |build: &str| PackageCandidate {
+                build: Some(build.to_string()),
+                ..test_candidate("python", "3.10.0")
+            };
+
+            let ms: MatchSpec = "python 3.10 py3*".parse().unwrap();
+            assert_eq!(ms.build, Some("py3*".to_string()));
+            assert!(ms.is_match(&candidate("py39h_0")));
+            assert!(ms.is_match(&candidate("py310h6a678d5_0")));
+            assert!(!ms.is_match(&candidate("h12debd9_0")));
+
+            // The glob works the same in brackets, and exact builds still have to be exact
+            let ms: MatchSpec = "python>=3.10[build='*_0']".parse().unwrap();
+            assert!(ms.is_match(&candidate("h12debd9_0")));
+            assert!(!ms.is_match(&candidate("h12debd9_1")));
+            let ms: MatchSpec = "python 3.10 py39h_0".parse().unwrap();
+            assert!(!ms.is_match(&candidate("py39h_01")));
+        }
+
+        #[test]
+        fn build_glob_without_build() {
+            let pc = PackageCandidate {
+                build: None,
+                ..test_candidate("python", "3.10.0")
+            };
+
+            // A missing build is an empty one, which only a glob matching anything accepts
+            for spec in ["python[build=*]", "python 3.10.0 *", "python=3.10.0=*", "python[build='**']"] {
+                assert!(spec.parse::<MatchSpec>().unwrap().is_match(&pc), "{}", spec);
+            }
+            for spec in ["python[build=py3*]", "python[build='*_0']", "python 3.10.0 py310h_0"] {
+                assert!(!spec.parse::<MatchSpec>().unwrap().is_match(&pc), "{}", spec);
+            }
+        }
+
+        #[test]
+        fn simplify() {
+            let simplify = |spec: &str| spec.parse::<MatchSpec>().unwrap().simplify().to_string();
+
+            // Redundant lower bounds
+            assert_eq!(simplify("numpy>=1.0,>=1.2"), "numpy>=1.2");
+            assert_eq!(simplify("numpy>1.2,>=1.0"), "numpy>1.2");
+            assert_eq!(simplify("numpy>=1.2,>1.2"), "numpy>1.2");
+
+            // Redundant upper bounds
+            assert_eq!(simplify("numpy<2.0,<=3.0"), "numpy<2.0");
+            assert_eq!(simplify("numpy<=2.0,<2.0"), "numpy<2.0");
+
+            // Duplicates and exact versions inside a range
+            assert_eq!(simplify("numpy>=1.0,>=1.0.0"), "numpy>=1.0");
+            assert_eq!(simplify("numpy==1.5,<2.0"), "numpy==1.5");
+            assert_eq!(simplify("numpy>=1.0,==1.5"), "numpy==1.5");
+            assert_eq!(simplify("numpy[build_number='>=2,>=5']"), "numpy[build_number='>=5']");
+
+            // Nothing to simplify
+            assert_eq!(simplify("numpy>=1.0,<2.0"), "numpy>=1.0,<2.0");
+            assert_eq!(simplify("numpy==2.5,<2.0"), "numpy==2.5,<2.0");
+            assert_eq!(simplify("numpy<1.0|<2.0"), "numpy<1.0|<2.0");
+            assert_eq!(simplify("numpy>=1.0"), "numpy>=1.0");
+            assert_eq!(simplify("numpy"), "numpy");
+        }
+
+        #[test]
+        fn semantic_equality() {
+            let a: MatchSpec = "numpy==1.0".parse().unwrap();
+            let b: MatchSpec = "numpy==1.0.0".parse().unwrap();
+            assert_ne!(a, b);
+            assert!(a.semantically_eq(&b));
+            assert!(b.semantically_eq(&a));
+
+            let a: MatchSpec = "numpy>=1.0,<2".parse().unwrap();
+            let b: MatchSpec = "numpy>=1.0.0,<2.0.0".parse().unwrap();
+            assert!(a.semantically_eq(&b));
+
+            // Different selectors, joiners, or packages are never equal
+            let b: MatchSpec = "numpy>1.0,<2".parse().unwrap();
+            assert!(!a.semantically_eq(&b));
+            let b: MatchSpec = "numpy>=1.0|<2".parse().unwrap();
+            assert!(!a.semantically_eq(&b));
+            let b: MatchSpec = "scipy>=1.0,<2".parse().unwrap();
+            assert!(!a.semantically_eq(&b));
+
+            // `==1.0` and `>=1.0,<1.1` are different constraints
+            let a: MatchSpec = "numpy==1.0".parse().unwrap();
+            let b: MatchSpec = "numpy>=1.0,<1.1".parse().unwrap();
+            assert!(!a.semantically_eq(&b));
+
+            let a: MatchSpec = "numpy[build_number=2]".parse().unwrap();
+            let b: MatchSpec = "numpy[build_number=2.0]".parse().unwrap();
+            let c: MatchSpec = "numpy[build_number=3]".parse().unwrap();
+            assert!(a.semantically_eq(&b));
+            assert!(!a.semantically_eq(&c));
+        }
+
+        #[test]
+        fn range_overlaps() {
+            let overlaps = |a: &str, b: &str| {
+                let a: CompoundSelector<String> = a.parse().unwrap();
+                let b: CompoundSelector<String> = b.parse().unwrap();
+                a.version_range_overlaps(&b) && b.version_range_overlaps(&a)
+            };
+
+            assert!(overlaps(">=1.0,<2.0", ">=1.5,<3.0"));
+            assert!(overlaps(">=1.0,<2.0", "==1.2.3"));
+            assert!(overlaps(">=1.0", "<=1.0"));
+            assert!(overlaps("<1.0|>2.0", ">=2.5,<3.0"));
+            assert!(overlaps("!=1.5", ">=1.5,<=1.5.1"));
+
+            assert!(!overlaps(">=1.0,<2.0", ">=2.0,<3.0"));
+            assert!(!overlaps(">1.0", "<1.0"));
+            assert!(!overlaps(">=2.0", "<1.0"));
+            assert!(!overlaps("<1.0|>2.0", ">=1.2,<1.8"));
+            assert!(!overlaps("!=1.5", "==1.5"));
+        }
+
+        #[test]
+        fn build_metadata() {
+            let ms: MatchSpec = "pkg==1.2.3+a".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.2.3+a"));
+            assert!(ms.is_package_version_match("pkg", "1.2.3+b"));
+            assert!(ms.is_package_version_match("pkg", "1.2.3"));
+            assert!(!ms.is_package_version_match("pkg", "1.2.4+a"));
+
+            let ms: MatchSpec = "pkg===1.2.3+a".parse().unwrap();
+            assert_eq!(
+                ms.version,
+                Some(CompoundSelector::Single {
+                    selector: Selector::ExactlyEqualTo,
+                    version: "1.2.3+a".to_string(),
+                })
+            );
+            assert!(ms.is_package_version_match("pkg", "1.2.3+a"));
+            assert!(!ms.is_package_version_match("pkg", "1.2.3+b"));
+            assert!(!ms.is_package_version_match("pkg", "1.2.3"));
+
+            // Ordering ignores the metadata too
+            let ms: MatchSpec = "pkg>=1.2.3,<1.2.4".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.2.3+build5"));
+            let ms: MatchSpec = "pkg!=1.2.3".parse().unwrap();
+            assert!(!ms.is_package_version_match("pkg", "1.2.3+build5"));
+        }
+
+        #[test]
+        fn underscore_versions() {
+            // Underscores separate version segments the same way that periods do
+            let ms: MatchSpec = "pkg>1.21_5".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.21_6"));
+            assert!(!ms.is_package_version_match("pkg", "1.21_5"));
+            assert!(!ms.is_package_version_match("pkg", "1.21"));
+
+            let ms: MatchSpec = "pkg<1.21_5".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.21"));
+            assert!(ms.is_package_version_match("pkg", "1.21_4"));
+            assert!(!ms.is_package_version_match("pkg", "1.21_6"));
+
+            let ms: MatchSpec = "pkg==1.21_5".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.21_5"));
+            assert!(ms.is_package_version_match("pkg", "1.21.5"));
+        }
+
+        #[test]
+        fn version_selector() {
+            let selector = |s: &str| s.parse::<MatchSpec>().unwrap().version_selector().cloned();
+            assert_eq!(selector("numpy>=1.20"), Some(Selector::GreaterThanOrEqualTo));
+            assert_eq!(selector("numpy!=1.20"), Some(Selector::NotEqualTo));
+            assert_eq!(selector("numpy 1.20"), Some(Selector::EqualTo));
+            assert_eq!(selector("numpy>=1.20,<2"), None);
+            assert_eq!(selector("numpy<1|>2"), None);
+            assert_eq!(selector("numpy"), None);
+        }
+
+        #[test]
+        fn latest_request() {
+            let latest = |s: &str| s.parse::<MatchSpec>().unwrap().is_latest_request();
+            assert!(latest("numpy=*"));
+            assert!(latest("numpy==*"));
+            assert!(latest("numpy *"));
+            assert!(latest("conda-forge::numpy=*[build=py39*]"));
+            assert!(!latest("numpy"));
+            assert!(!latest("numpy==1.0"));
+            assert!(!latest("numpy=1.*"));
+            assert!(!latest("numpy>=1.0"));
+            assert!(!latest("numpy!=*"));
+        }
+
+        #[test]
+        fn build_and_build_number() {
+            let ms: MatchSpec = "python[build=py3*,build_number='>=2']".parse().unwrap();
+            assert!(ms.is_build_and_number_match(Some("py310h_2"), &Some(2)));
+            assert!(!ms.is_build_and_number_match(Some("py310h_2"), &None));
+            assert!(!ms.is_build_and_number_match(None, &Some(2)));
+            assert!(!ms.is_build_and_number_match(None, &None));
+
+            // The wrong build or build_number
+            assert!(!ms.is_build_and_number_match(Some("h12debd9_2"), &Some(2)));
+            assert!(!ms.is_build_and_number_match(Some("py310h_1"), &Some(1)));
+
+            // Without any constraints everything matches
+            let ms: MatchSpec = "python 3.10".parse().unwrap();
+            assert!(ms.is_build_and_number_match(Some("py310h_2"), &Some(2)));
+            assert!(ms.is_build_and_number_match(Some("py310h_2"), &None));
+            assert!(ms.is_build_and_number_match(None, &Some(2)));
+            assert!(ms.is_build_and_number_match(None, &None));
+        }
+
+        #[test]
+        fn post_and_dev_versions() {
+            let ms: MatchSpec = "numpy>=1.0".parse().unwrap();
+            assert!(!ms.is_version_match("1.0.dev1"));
+            assert!(ms.is_version_match("1.0.post1"));
+            assert!(ms.is_version_match("1.0"));
+
+            let ms: MatchSpec = "numpy<1.0".parse().unwrap();
+            assert!(ms.is_version_match("1.0.dev1"));
+            assert!(!ms.is_version_match("1.0.post1"));
+            assert!(ms.is_version_match("0.9.post3"));
+        }
+
+        #[test]
+        fn letter_versions() {
+            // A trailing letter makes a pre-release, like openssl's `1.1.1a`
+            let ms: MatchSpec = "openssl>=1.1.1".parse().unwrap();
+            assert!(!ms.is_version_match("1.1.1a"));
+            assert!(ms.is_version_match("1.1.1"));
+            assert!(ms.is_version_match("1.1.2a"));
+
+            let ms: MatchSpec = "openssl<1.1.1".parse().unwrap();
+            assert!(ms.is_version_match("1.1.1a"));
+            assert!(ms.is_version_match("1.1.1rc1"));
+            assert!(!ms.is_version_match("1.1.1"));
+
+            // Both sides having letters compares the letters
+            let ms: MatchSpec = "openssl>1.1.1a".parse().unwrap();
+            assert!(ms.is_version_match("1.1.1q"));
+            assert!(!ms.is_version_match("1.1.1a"));
+            assert!(!ms.is_version_match("1.1.0z"));
+
+            assert!("openssl==1.1.1".parse::<MatchSpec>().unwrap().is_version_match("1.1.1.0"));
+            assert!(!"openssl==1.1.1".parse::<MatchSpec>().unwrap().is_version_match("1.1.1a"));
+        }
+
+        #[test]
+        fn compatible_release() {
+            // Three segments, `>=1.2.3,<1.3`
+            let ms: MatchSpec = "pkg~=1.2.3".parse().unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("~=", "1.2.3"))));
+            assert!(ms.is_version_match("1.2.3"));
+            assert!(ms.is_version_match("1.2.10"));
+            assert!(!ms.is_version_match("1.2.2"));
+            assert!(!ms.is_version_match("1.3.0"));
+            assert_eq!(ms.to_string(), "pkg~=1.2.3");
+
+            // Two segments, `>=1.2,<2`
+            let ms: MatchSpec = "pkg~=1.2".parse().unwrap();
+            assert!(ms.is_version_match("1.2"));
+            assert!(ms.is_version_match("1.9.5"));
+            assert!(!ms.is_version_match("1.1"));
+            assert!(!ms.is_version_match("2.0"));
+
+            // One segment has no upper bound to derive, so it's an error like in PEP 440
+            assert!("pkg~=1".parse::<MatchSpec>().is_err());
+            assert!("pkg>=0.5,~=1".parse::<MatchSpec>().is_err());
+
+            let ms: MatchSpec = "pkg~=1.2.3,!=1.2.5".parse().unwrap();
+            assert!(ms.is_version_match("1.2.4"));
+            assert!(!ms.is_version_match("1.2.5"));
+
+            let other: MatchSpec = "pkg>=1.3".parse().unwrap();
+            assert!("pkg~=1.2.3".parse::<MatchSpec>().unwrap().conflicts_with(&other));
+            assert!(!"pkg~=1.2".parse::<MatchSpec>().unwrap().conflicts_with(&other));
+        }
+
+        #[test]
+        fn epochs() {
+            let ms: MatchSpec = "pkg>=1!1.0,<2!0".parse().unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from((">=", "1!1.0", ",", "<", "2!0"))));
+            assert!(ms.is_version_match("1!5.0"));
+            assert!(ms.is_version_match("1!1.0"));
+            assert!(!ms.is_version_match("2!0.1"));
+            assert!(!ms.is_version_match("2023.1"));
+            assert_eq!(ms.to_string(), "pkg>=1!1.0,<2!0");
+
+            // `!=` still parses as an operator after the name
+            let ms: MatchSpec = "pkg !=1.0".parse().unwrap();
+            assert!(ms.is_version_match("1!1.0"));
+            assert!(!ms.is_version_match("1.0"));
+        }
+
+        #[test]
+        fn prefix_versions() {
+            let ms: MatchSpec = "python 2.7.*".parse().unwrap();
+            assert!(ms.is_version_match("2.7"));
+            assert!(ms.is_version_match("2.7.18"));
+            assert!(!ms.is_version_match("2.8"));
+            assert!(!ms.is_version_match("2.70"));
+
+            let ms: MatchSpec = "python!=2.7.*".parse().unwrap();
+            assert!(!ms.is_version_match("2.7.18"));
+            assert!(ms.is_version_match("3.10.0"));
+
+            let ms: MatchSpec = "openssl 1.*.1*".parse().unwrap();
+            assert!(ms.is_version_match("1.1.1g"));
+            assert!(!ms.is_version_match("3.0.8"));
+        }
+
+        #[test]
+        fn prefix_ranges() {
+            // Both the implicit and explicit forms keep the glob, which matches the whole series
+            for spec in ["numpy 1.11.*", "numpy=1.11.*", "numpy==1.11.*", "numpy[version='1.11.*']"] {
+                let ms: MatchSpec = spec.parse().unwrap();
+                assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.11.*"))), "{}", spec);
+                assert!(ms.is_version_match("1.11"), "{}", spec);
+                assert!(ms.is_version_match("1.11.3"), "{}", spec);
+                assert!(ms.is_version_match("1.11.0a1"), "{}", spec);
+                assert!(!ms.is_version_match("1.12"), "{}", spec);
+                assert!(!ms.is_version_match("1.10.9"), "{}", spec);
+                // Pre-releases of the next series sort below it, but aren't part of this one
+                for version in ["1.12a1", "1.12.0rc1", "1.12.dev0", "1.11a1"] {
+                    assert!(!ms.is_version_match(version), "{} {}", spec, version);
+                    assert!(!MatchSpec::parse_borrowed(spec).unwrap().is_version_match(version), "{} {}", spec, version);
+                }
+            }
+
+            let ms: MatchSpec = "numpy 1.*".parse().unwrap();
+            assert!(ms.is_version_match("1.26.4"));
+            assert!(!ms.is_version_match("2.0a1"));
+            assert!("pkg=1!2.*".parse::<MatchSpec>().unwrap().is_version_match("1!2.5"));
+
+            // The glob is kept when rendering, so the spec round trips
+            let ms: MatchSpec = "numpy 1.11.*".parse().unwrap();
+            assert_eq!(ms.to_string(), "numpy==1.11.*");
+            assert_eq!(ms.to_string().parse::<MatchSpec>().unwrap(), ms);
+
+            // The series is still a range when comparing constraints
+            let ms: MatchSpec = "numpy 1.11.*".parse().unwrap();
+            assert!(ms.conflicts_with(&"numpy>=1.12".parse().unwrap()));
+            assert!(!ms.conflicts_with(&"numpy>=1.11.5".parse().unwrap()));
+
+            // A version without the `.*` stays exact
+            let ms: MatchSpec = "numpy 1.11".parse().unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.11"))));
+            assert!(ms.is_version_match("1.11.0"));
+            assert!(!ms.is_version_match("1.11.3"));
+        }
+
+        #[test]
+        fn series_candidate_versions() {
+            // The candidate `1.2.*` is every version from 1.2 up to 1.3
+            assert!("pkg>=1.2".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!("pkg>=1.2.5".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!("pkg<1.2.1".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!("pkg 1.2.*".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!(!"pkg>=1.3".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!(!"pkg<=1.1.9".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            // Pre-releases like 1.2.0a1 are part of the series and sort below 1.2
+            assert!("pkg<1.2".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!(!"pkg>=1.3a1".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+            assert!(!"pkg==1.1.9".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+
+            let pc = test_candidate("pkg", "1.2.*");
+            assert!("pkg>=1.2".parse::<MatchSpec>().unwrap().is_match(&pc));
+            assert!(!"pkg>=2".parse::<MatchSpec>().unwrap().is_match(&pc));
+
+            // Specs without a version match anything
+            assert!("pkg".parse::<MatchSpec>().unwrap().is_version_match("1.2.*"));
+        }
+
+        #[test]
+        fn differing_segment_counts() {
+            // Missing segments are treated as zero, like conda does
+            let ms: MatchSpec = "pkg==1".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.0.0"));
+            assert!(!ms.is_package_version_match("pkg", "1.0.1"));
+
+            let ms: MatchSpec = "pkg==1.2".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.2.0.0"));
+
+            let ms: MatchSpec = "pkg!=1.0".parse().unwrap();
+            assert!(!ms.is_package_version_match("pkg", "1.0.0"));
+
+            let ms: MatchSpec = "pkg>=1.2.0.0,<2".parse().unwrap();
+            assert!(ms.is_package_version_match("pkg", "1.2"));
+            assert!(!ms.is_package_version_match("pkg", "2.0.0"));
+
+            // Version ranges agree with the matching
+            let a: CompoundSelector<String> = "<=1".parse().unwrap();
+            let b: CompoundSelector<String> = ">=1.0.0".parse().unwrap();
+            assert!(a.version_range_overlaps(&b));
+        }
+
+        #[test]
+        fn test_version_compare() {
+            let ms: MatchSpec = "python>3.6".parse().unwrap();
+            assert!(!ms.is_package_version_match("python", "3.5"));
+            assert!(ms.is_package_version_match("python", "3.8"));
+            assert!(ms.is_package_version_match("python", "3.9"));
+            assert!(ms.is_package_version_match("python", "3.10"));
+        }
+    }
+
+    #[cfg(test)]
+    mod best_match {
+        use crate::matchspec::*;
+
+        #[test]
+        fn newest_build() {
+            let candidate = |version: &str, build_number: u32| PackageCandidate {
+                build_number: Some(build_number),
+                ..test_candidate("python", version)
+            };
+            let candidates = vec![
+                candidate("3.9.18", 0),
+                candidate("3.10.13", 0),
+                candidate("3.10.13", 2),
+                candidate("3.10.13", 1),
+                candidate("3.8.18", 5),
+                candidate("3.12.1", 0),
+            ];
+
+            let best = |spec: &str| {
+                let ms: MatchSpec = spec.parse().unwrap();
+                ms.best_match(&candidates)
+                    .map(|pc| (pc.version.clone().unwrap(), pc.build_number.unwrap()))
+            };
+
+            assert_eq!(best("python>=3.9,<3.11"), Some(("3.10.13".to_string(), 2)));
+            assert_eq!(best("python"), Some(("3.12.1".to_string(), 0)));
+            assert_eq!(best("python<3"), None);
+        }
+    }
+
+    #[cfg(test)]
+    mod dependents {
+        use crate::matchspec::*;
+
+        #[test]
+        fn only_matching_depends() {
+            let candidates: Vec<PackageCandidate> = [
+                r#"{"name": "numpy", "depends": ["python >=3.9,<3.10.0a0", "libopenblas"]}"#,
+                r#"{"name": "six", "depends": ["python"]}"#,
+                r#"{"name": "futures", "depends": ["python <3"]}"#,
+                r#"{"name": "zlib", "depends": ["libgcc-ng >=11.2.0"]}"#,
+                r#"{"name": "python", "version": "3.9.16"}"#,
+            ]
+            .into_iter()
+            .map(PackageCandidate::from)
+            .collect();
+
+            let names = |spec: &str| -> Vec<String> {
+                let ms: MatchSpec = spec.parse().unwrap();
+                ms.dependents(&candidates).iter().map(|pc| pc.name.clone()).collect()
+            };
+
+            assert_eq!(names("python=3.9"), vec!["numpy", "six"]);
+            assert_eq!(names("python=2.7"), vec!["six", "futures"]);
+            assert_eq!(names("python"), vec!["numpy", "six", "futures"]);
+            assert!(names("pandas").is_empty());
+        }
+    }
+
+    #[cfg(test)]
+    mod matching_subdirs {
+        use crate::matchspec::*;
+
+        #[test]
+        fn multiple_subdirs() {
+            let candidates: Vec<PackageCandidate> = [
+                r#"{"name": "numpy", "version": "1.26.4", "subdir": "linux-64"}"#,
+                r#"{"name": "numpy", "version": "1.26.4", "subdir": "osx-arm64"}"#,
+                r#"{"name": "numpy", "version": "1.21.0", "subdir": "win-64"}"#,
+                r#"{"name": "numpy", "version": "1.26.0", "subdir": "linux-64"}"#,
+                r#"{"name": "numpy", "version": "1.26.4"}"#,
+                r#"{"name": "scipy", "version": "1.11.4", "subdir": "linux-aarch64"}"#,
+            ]
+            .into_iter()
+            .map(PackageCandidate::from)
+            .collect();
+
+            let subdirs = |spec: &str| -> Vec<String> {
+                let ms: MatchSpec = spec.parse().unwrap();
+                ms.matching_subdirs(&candidates).into_iter().collect()
+            };
+
+            // Each subdir is only listed once, and a candidate without one is skipped
+            assert_eq!(subdirs("numpy>=1.26"), vec!["linux-64", "osx-arm64"]);
+            assert_eq!(subdirs("numpy"), vec!["linux-64", "osx-arm64", "win-64"]);
+            assert_eq!(subdirs("*"), vec!["linux-64", "linux-aarch64", "osx-arm64", "win-64"]);
+            assert!(subdirs("numpy<1.0").is_empty());
+        }
+    }
+
+    #[cfg(test)]
+    mod filter_index {
+        use crate::matchspec::*;
+
+        fn index() -> HashMap<String, Vec<PackageCandidate>> {
+            let mut index: HashMap<String, Vec<PackageCandidate>> = HashMap::new();
+            for (name, version) in [("numpy", "1.21.0"), ("numpy", "1.26.4"), ("numpy-base", "1.26.4"), ("scipy", "1.11.4")] {
+                index.entry(name.to_string()).or_default().push(test_candidate(name, version));
+            }
+            // A misfiled candidate, which is only found if the scipy bucket gets scanned
+            index.get_mut("scipy").unwrap().push(test_candidate("numpy", "1.25.0"));
+            index
+        }
+
+        #[test]
+        fn literal_package() {
+            let index = index();
+            let versions = |spec: &str| {
+                let ms: MatchSpec = spec.parse().unwrap();
+                let mut versions: Vec<&str> = ms
+                    .filter_index(&index)
+                    .iter()
+                    .map(|pc| pc.version.as_deref().unwrap())
+                    .collect();
+                versions.sort();
+                versions
+            };
+
+            assert_eq!(versions("numpy"), vec!["1.21.0", "1.26.4"]);
+            assert_eq!(versions("numpy>=1.22"), vec!["1.26.4"]);
+            assert!(versions("pandas").is_empty());
+        }
+
+        #[test]
+        fn glob_package() {
+            let index = index();
+            let ms: MatchSpec = "numpy*>=1.22".parse().unwrap();
+            let mut found: Vec<(&str, &str)> = ms
+                .filter_index(&index)
+                .iter()
+                .map(|pc| (pc.name.as_str(), pc.version.as_deref().unwrap()))
+                .collect();
+            found.sort();
+
+            // Globs have to look through every bucket
+            assert_eq!(found, vec![("numpy", "1.25.0"), ("numpy", "1.26.4"), ("numpy-base", "1.26.4")]);
+        }
+    }
+
+    #[cfg(test)]
+    mod match_options {
+        use crate::matchspec::*;
+
+        fn candidate(name: &str, version: &str) -> PackageCandidate {
+            PackageCandidate {
+                build: Some("py39h_0".to_string()),
+                channel: Some("conda-forge".to_string()),
+                ..test_candidate(name, version)
+            }
+        }
+
+        #[test]
+        fn defaults_match_is_match() {
+            let ms: MatchSpec = "conda-forge::numpy>=1.20[build=py39h_0]".parse().unwrap();
+            let pc = candidate("numpy", "1.21.0");
+            assert!(ms.is_match(&pc));
+            assert!(ms.is_match_with(&pc, &MatchOptions::default()));
+        }
+
+        #[test]
+        fn case_insensitive() {
+            let ms: MatchSpec = "pyyaml*[exclude=pyyaml-ext]".parse().unwrap();
+            let options = MatchOptions { case_insensitive: true, ..MatchOptions::default() };
+
+            assert!(!ms.is_match(&candidate("PyYAML", "6.0")));
+            assert!(ms.is_match_with(&candidate("PyYAML", "6.0"), &options));
+            assert!(!ms.is_match_with(&candidate("PyYAML-Ext", "6.0"), &options));
+        }
+
+        #[test]
+        fn ignore_build() {
+            let ms: MatchSpec = "numpy[build=py310h_0]".parse().unwrap();
+            let options = MatchOptions { ignore_build: true, ..MatchOptions::default() };
+
+            assert!(!ms.is_match(&candidate("numpy", "1.21.0")));
+            assert!(ms.is_match_with(&candidate("numpy", "1.21.0"), &options));
+        }
+
+        #[test]
+        fn allow_prerelease() {
+            let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+            let options = MatchOptions { allow_prerelease: false, ..MatchOptions::default() };
+
+            assert!(ms.is_match(&candidate("numpy", "2.0.0rc1")));
+            assert!(!ms.is_match_with(&candidate("numpy", "2.0.0rc1"), &options));
+            assert!(!ms.is_match_with(&candidate("numpy", "2.0.0.dev0"), &options));
+            assert!(ms.is_match_with(&candidate("numpy", "2.0.0"), &options));
+
+            // Letters that aren't pre-release tags are fine
+            let ms: MatchSpec = "openssl>=1.1.0".parse().unwrap();
+            assert!(ms.is_match_with(&candidate("openssl", "1.1.1g"), &options));
+
+            // Asking for a pre-release opts in to them
+            let ms: MatchSpec = "numpy>=2.0.0rc1".parse().unwrap();
+            assert!(ms.is_match_with(&candidate("numpy", "2.0.0rc2"), &options));
+        }
+
+        #[test]
+        fn ignore_channel() {
+            let ms: MatchSpec = "defaults::numpy".parse().unwrap();
+            let options = MatchOptions { ignore_channel: false, ..MatchOptions::default() };
+
+            // The channel is only checked when asked for
+            assert!(ms.is_match(&candidate("numpy", "1.21.0")));
+            assert!(!ms.is_match_with(&candidate("numpy", "1.21.0"), &options));
+
+            // A candidate without a channel can't be ruled out
+            let pc = PackageCandidate { channel: None, ..candidate("numpy", "1.21.0") };
+            assert!(ms.is_match_with(&pc, &options));
+        }
+
+        #[test]
+        fn channel_and_subdir_only() {
+            let ms: MatchSpec = "conda-forge/linux-64::*".parse().unwrap();
+            assert_eq!(ms.channel.as_deref(), Some("conda-forge"));
+            assert_eq!(ms.subdir.as_deref(), Some("linux-64"));
+            assert_eq!(ms.package, "*");
+            assert_eq!(ms.version, None);
+            assert_eq!(ms.to_string(), "conda-forge/linux-64::*");
+
+            let on = |name: &str, channel: &str, subdir: &str| PackageCandidate {
+                subdir: Some(subdir.to_string()),
+                channel: Some(channel.to_string()),
+                ..candidate(name, "1.0")
+            };
+            let options = MatchOptions { ignore_channel: false, ..MatchOptions::default() };
+            assert!(ms.is_match_with(&on("numpy", "conda-forge", "linux-64"), &options));
+            assert!(ms.is_match_with(&on("_libgcc_mutex", "conda-forge", "linux-64"), &options));
+            assert!(!ms.is_match_with(&on("numpy", "defaults", "linux-64"), &options));
+            assert!(!ms.is_match_with(&on("numpy", "conda-forge", "osx-arm64"), &options));
+            assert!(ms.is_match(&on("numpy", "defaults", "linux-64")));
+        }
+
+        #[test]
+        fn channel_list() {
+            let ms: MatchSpec = "conda-forge,defaults::numpy>=1.20".parse().unwrap();
+            assert_eq!(ms.channel, Some("conda-forge".to_string()));
+            assert_eq!(ms.channels, vec!["conda-forge".to_string(), "defaults".to_string()]);
+            assert_eq!(ms.to_string(), "conda-forge,defaults::numpy>=1.20");
+
+            let from = |channel: &str| PackageCandidate {
+                channel: Some(channel.to_string()),
+                ..candidate("numpy", "1.21.0")
+            };
+            let options = MatchOptions { ignore_channel: false, ..MatchOptions::default() };
+            assert!(ms.is_match_with(&from("conda-forge"), &options));
+            assert!(ms.is_match_with(&from("defaults"), &options));
+            assert!(!ms.is_match_with(&from("bioconda"), &options));
+
+            // A single channel doesn't fill in the list
+            let ms: MatchSpec = "conda-forge/linux-64::numpy".parse().unwrap();
+            assert!(ms.channels.is_empty());
+            let ms: MatchSpec = "conda-forge,defaults/linux-64::numpy".parse().unwrap();
+            assert_eq!(ms.channels.len(), 2);
+            assert_eq!(ms.subdir, Some("linux-64".to_string()));
+        }
+    }
+
+    #[cfg(test)]
+    mod from_str {
+        use crate::error::MatchSpecError;
+        use crate::matchspec::*;
+
+        #[test]
+        fn selector_valid() {
+            assert_eq!(">".parse(), Ok(Selector::GreaterThan));
+            assert_eq!(">=".parse(), Ok(Selector::GreaterThanOrEqualTo));
+            assert_eq!("<".parse(), Ok(Selector::LessThan));
+            assert_eq!("<=".parse(), Ok(Selector::LessThanOrEqualTo));
+            assert_eq!("!=".parse(), Ok(Selector::NotEqualTo));
+            assert_eq!("==".parse(), Ok(Selector::EqualTo));
+            assert_eq!("=".parse(), Ok(Selector::EqualTo));
+            assert_eq!("===".parse(), Ok(Selector::ExactlyEqualTo));
+            assert_eq!(" >= ".parse(), Ok(Selector::GreaterThanOrEqualTo));
+        }
+
+        #[test]
+        fn selector_invalid() {
+            assert_eq!(
+                "garbage".parse::<Selector>(),
+                Err(MatchSpecError {
+                    message: "Invalid selector 'garbage'".to_string()
+                })
+            );
+            assert!("".parse::<Selector>().is_err());
+            assert!("=>".parse::<Selector>().is_err());
+            assert!(">=1.0".parse::<Selector>().is_err());
+        }
+
+        #[test]
+        fn parse_limits() {
+            let long = format!("numpy>=1.20[license='{}']", "a".repeat(5000));
+            assert_eq!(
+                long.parse::<MatchSpec>(),
+                Err(MatchSpecError {
+                    message: "MatchSpec is 5023 bytes long, the limit is 4096".to_string()
+                })
+            );
+
+            let clauses = format!("numpy>=1.20{}", "|<1.0".repeat(300));
+            assert_eq!(
+                clauses.parse::<MatchSpec>(),
+                Err(MatchSpecError {
+                    message: "MatchSpec has 301 clauses, the limit is 256".to_string()
+                })
+            );
+
+            let options = ParseOptions {
+                max_length: 64,
+                max_clauses: 2,
+                ..ParseOptions::default()
+            };
+            assert!(MatchSpec::parse_with("numpy>=1.20,<2.0", &options).is_ok());
+            assert!(MatchSpec::parse_with("numpy>=1.20,<2.0[subdir=linux-64,build=a]", &options).is_err());
+            assert!(MatchSpec::parse_with(&"a".repeat(65), &options).is_err());
+
+            // Quoted values, channel lists and comments don't use up clauses
+            for spec in [
+                "numpy>=1.20,<2.0[license='a, b, c']",
+                "numpy>=1.20,<2.0 # a|b",
+                "conda-forge,defaults::numpy>=1.20,<2.0",
+                "numpy>=1.20||<1.0",
+            ] {
+                assert!(MatchSpec::parse_with(spec, &options).is_ok(), "{}", spec);
+            }
+            assert_eq!(count_clauses("numpy[license='a, b, c', md5=\"x|y\"]"), 2);
+            assert_eq!(count_clauses("conda-forge,defaults/linux-64::numpy>=1|<0.5"), 2);
+            assert!(MatchSpec::is_valid(&format!("numpy[license='{}']", ",".repeat(300))));
+        }
 
         #[test]
-        fn package_only() {
-            let mut ms: MatchSpec = "tensorflow".parse().unwrap();
+        fn parse_error_context() {
+            assert_eq!(
+                "conda-forge::".parse::<MatchSpec>(),
+                Err(MatchSpecError {
+                    message: "Unable to parse MatchSpec 'conda-forge::' at offset 13, remaining input ''"
+                        .to_string()
+                })
+            );
+            assert_eq!(
+                "[build=1]".parse::<MatchSpec>(),
+                Err(MatchSpecError {
+                    message: "Unable to parse MatchSpec '[build=1]' at offset 0, remaining input '[build=1]'"
+                        .to_string()
+                })
+            );
 
-            assert!(ms.is_package_match("tensorflow".to_string()));
-            assert!(!ms.is_package_match("pytorch".to_string()));
+            // Errors from parse_borrowed carry the same context
+            let err = MatchSpec::parse_borrowed("conda-forge::").unwrap_err();
+            assert!(err.message.contains("'conda-forge::'"));
+        }
 
-            ms = "tensor*-gpu".parse().unwrap();
-            assert!(ms.is_package_match("tensorflow-gpu".to_string()));
-            assert!(!ms.is_package_match("tennnnsorflow-gpu".to_string()));
+        #[test]
+        fn lenient() {
+            let (ms, remainder) = MatchSpec::parse_lenient("numpy>=1.20,<2.0 !!garbage");
+            assert_eq!(ms, "numpy>=1.20,<2.0".parse::<MatchSpec>().unwrap());
+            assert_eq!(ms.raw(), Some("numpy>=1.20,<2.0"));
+            assert_eq!(remainder, Some("!!garbage".to_string()));
 
-            ms = "tensorflow*".parse().unwrap();
-            assert!(ms.is_package_match("tensorflow".to_string()));
-            assert!(ms.is_package_match("tensorflow-gpu".to_string()));
+            // Key values that aren't attached to the spec are left over
+            let (ms, remainder) = MatchSpec::parse_lenient("conda-forge::numpy>=1.20 [subdir=linux-64]");
+            assert_eq!(ms.channel, Some("conda-forge".to_string()));
+            assert_eq!(ms.subdir, None);
+            assert_eq!(remainder, Some("[subdir=linux-64]".to_string()));
 
-            ms = "*-gpu".parse().unwrap();
-            assert!(ms.is_package_match("tensorflow-gpu".to_string()));
-            assert!(!ms.is_package_match("tensorflow".to_string()));
+            // A version that doesn't parse leaves just the name
+            let (ms, remainder) = MatchSpec::parse_lenient("python=wrong");
+            assert_eq!(ms.package, "python");
+            assert_eq!(ms.version, None);
+            assert_eq!(remainder, Some("=wrong".to_string()));
 
-            // Illegal chars
-            assert!(!ms.is_package_match("python>3.10[name=* vmd5=\"abcdef1312\"]".to_string()));
+            // Valid specs have nothing left over, and comments aren't garbage
+            let (ms, remainder) = MatchSpec::parse_lenient("zstd 1.4.5 h9ceee32_0 # pinned");
+            assert_eq!(ms, "zstd 1.4.5 h9ceee32_0".parse::<MatchSpec>().unwrap());
+            assert_eq!(remainder, None);
+
+            // Nothing to parse at all
+            let (ms, remainder) = MatchSpec::parse_lenient("[build=1]");
+            assert_eq!(ms.package, "");
+            assert!(!ms.is_package_match("numpy".to_string()));
+            assert_eq!(remainder, Some("[build=1]".to_string()));
+            assert_eq!(MatchSpec::parse_lenient("").1, None);
         }
 
         #[test]
-        fn package_and_version_only() {
-            let ms: MatchSpec = "tensorflow>1.9.2".parse().unwrap();
+        fn operator_typos() {
+            let message = |s: &str| s.parse::<MatchSpec>().unwrap_err().message;
+            assert_eq!(
+                message("numpy=>1.0"),
+                "Unable to parse MatchSpec 'numpy=>1.0' at offset 5, remaining input '=>1.0', did you mean `>=`?"
+            );
+            assert!(message("numpy=<1.0").ends_with("did you mean `<=`?"));
+            assert!(message("numpy>>1.0").ends_with("did you mean `>`?"));
+            assert!(message("numpy>=1.0,=<2.0").contains("at offset 11"));
+            assert!(MatchSpec::parse_borrowed("numpy=>1.0").unwrap_err().message.ends_with("did you mean `>=`?"));
+            assert!(!MatchSpec::is_valid("numpy=>1.0"));
 
-            assert!(ms.is_package_version_match("tensorflow", "1.9.3"));
-            assert!(!ms.is_package_version_match("tensorflow", "1.9.0"));
+            // The correct operators are fine
+            assert!("numpy>=1.0,<=2.0".parse::<MatchSpec>().is_ok());
+
+            // Values in the brackets aren't operators
+            let ms: MatchSpec = "numpy[url='https://a/b=>c']".parse().unwrap();
+            assert_eq!(ms.key_values().get("url"), Some(&&CompoundSelector::from(("=", "https://a/b=>c"))));
+            assert!(MatchSpec::is_valid("numpy[url='https://a/b=>c']"));
+            assert!("numpy>=1.0[md5=\"a>>b\"]".parse::<MatchSpec>().is_ok());
+            assert!(message("numpy>>1.0[url='https://a/b=>c']").ends_with("did you mean `>`?"));
         }
 
         #[test]
-        fn compound_selectors() {
-            let single = CompoundSelector::Single {
-                selector: Selector::GreaterThan,
-                version: "1.1.1",
-            };
+        fn quoted_commas() {
+            let s = "numpy[license='Apache, 2.0', subdir=linux-64, md5=\"a,b]c\"]";
+            let ms: MatchSpec = s.parse().unwrap();
+            assert_eq!(ms.subdir, Some("linux-64".to_string()));
+            assert_eq!(
+                ms.key_value_pairs,
+                vec![
+                    ("license".to_string(), CompoundSelector::from(("=", "Apache, 2.0"))),
+                    ("subdir".to_string(), CompoundSelector::from(("=", "linux-64"))),
+                    ("md5".to_string(), CompoundSelector::from(("=", "a,b]c"))),
+                ]
+            );
+            assert!(MatchSpec::is_valid(s));
+            assert_eq!(MatchSpec::from(MatchSpec::parse_borrowed(s).unwrap()), ms);
 
-            assert!(single.is_match("1.2.1"));
-            assert!(single.is_match("3.0.0"));
-            assert!(!single.is_match("1.1.1"));
-            assert!(!single.is_match("0.1.1"));
+            // The rendered value is quoted again, so it round trips
+            assert_eq!(ms.to_string().parse::<MatchSpec>().unwrap(), ms);
+        }
 
-            let and = CompoundSelector::And {
-                first_selector: Selector::GreaterThan,
-                first_version: "1.1.1",
-                second_selector: Selector::LessThanOrEqualTo,
-                second_version: "1.2.1",
-            };
+        #[test]
+        fn unicode_whitespace() {
+            let expected: MatchSpec = "numpy >=1.20".parse().unwrap();
+            // A non-breaking space before the operator
+            assert_eq!("numpy\u{a0}>=1.20".parse::<MatchSpec>().unwrap(), expected);
+            assert_eq!("numpy\u{a0}>=\u{2009}1.20".parse::<MatchSpec>().unwrap(), expected);
+            assert!(MatchSpec::is_valid("numpy\u{a0}>=1.20"));
 
-            assert!(and.is_match("1.2.1"));
-            assert!(and.is_match("1.1.7"));
-            assert!(!and.is_match("1.2.2"));
-            assert!(!and.is_match("0.1.1"));
+            let ms: MatchSpec = "zstd\u{a0}1.4.5\u{a0}h9ceee32_0".parse().unwrap();
+            assert_eq!(ms.to_string(), "zstd==1.4.5[build=h9ceee32_0]");
+        }
 
-            let or = CompoundSelector::Or {
-                first_selector: Selector::LessThan,
-                first_version: "1.1.1",
-                second_selector: Selector::GreaterThan,
-                second_version: "1.2.1",
+        #[test]
+        fn validity() {
+            let specs = [
+                "numpy",
+                "numpy>=1.20,<2.0",
+                "zstd 1.4.5 h9ceee32_0",
+                "conda-forge/linux-64::numpy==1.21.0[build=py39h_0]",
+                "numpy # a comment",
+                "python=wrong",
+                "conda-forge::",
+                "[build=1]",
+                "",
+            ];
+
+            // is_valid always agrees with parse
+            for spec in specs {
+                assert_eq!(MatchSpec::is_valid(spec), spec.parse::<MatchSpec>().is_ok(), "{}", spec);
+            }
+
+            assert!(!MatchSpec::is_valid(&"a".repeat(5000)));
+            assert!(!MatchSpec::is_valid(&format!("numpy>=1.20{}", "|<1.0".repeat(300))));
+        }
+
+        #[test]
+        fn subdir_aliases() {
+            let options = ParseOptions { normalize_subdir: true, ..ParseOptions::default() };
+            let subdir = |s: &str| MatchSpec::parse_with(s, &options).unwrap().subdir;
+
+            assert_eq!(subdir("conda-forge/x86_64::numpy"), Some("linux-64".to_string()));
+            assert_eq!(subdir("numpy[subdir=64]"), Some("linux-64".to_string()));
+            assert_eq!(subdir("numpy[subdir=macos-arm64]"), Some("osx-arm64".to_string()));
+            assert_eq!(subdir("numpy[subdir=linux-arm64]"), Some("linux-aarch64".to_string()));
+            assert_eq!(subdir("numpy[subdir=windows-x64]"), Some("win-64".to_string()));
+            assert_eq!(subdir("numpy[subdir=linux-64]"), Some("linux-64".to_string()));
+            assert_eq!(subdir("numpy[subdir=noarch]"), Some("noarch".to_string()));
+            assert_eq!(subdir("numpy"), None);
+
+            // It's opt in
+            let ms: MatchSpec = "numpy[subdir=x86_64]".parse().unwrap();
+            assert_eq!(ms.subdir, Some("x86_64".to_string()));
+        }
+
+        #[test]
+        fn strict_keys() {
+            let options = ParseOptions { strict_keys: true, ..ParseOptions::default() };
+            assert_eq!(
+                MatchSpec::parse_with("numpy[subdr=linux-64]", &options),
+                Err(MatchSpecError {
+                    message: "Unknown key 'subdr' in MatchSpec 'numpy[subdr=linux-64]'".to_string()
+                })
+            );
+            assert!(MatchSpec::parse_with("numpy[build=py39*,colour=blue]", &options).is_err());
+
+            // Known keys are fine, in any case
+            let ms = MatchSpec::parse_with("numpy[Subdir=linux-64,license=BSD,md5=abc]", &options).unwrap();
+            assert_eq!(ms.subdir, Some("linux-64".to_string()));
+
+            // Lenient parsing keeps unknown keys around but doesn't use them
+            let ms: MatchSpec = "numpy[subdr=linux-64]".parse().unwrap();
+            assert_eq!(ms.subdir, None);
+            assert!(ms.key_values().contains_key("subdr"));
+        }
+
+        #[test]
+        fn package_name_only() {
+            let specs = [
+                ("numpy", "numpy"),
+                ("numpy 1.21.0 py39h_0", "numpy"),
+                ("numpy>=1.20,<2.0", "numpy"),
+                ("conda-forge::numpy", "numpy"),
+                ("conda-forge/linux-64::numpy[build=py39*]", "numpy"),
+                ("conda-forge/linux-64:ns:numpy==1.0", "numpy"),
+                ("tensorflow*[exclude=tensorflow-gpu]", "tensorflow*"),
+            ];
+            for (spec, name) in specs {
+                assert_eq!(MatchSpec::parse_package_name(spec), Ok(name.to_string()), "{}", spec);
+                assert_eq!(spec.parse::<MatchSpec>().unwrap().package, name);
+            }
+
+            assert!(MatchSpec::parse_package_name("[build=1]").is_err());
+        }
+
+        #[test]
+        fn legacy_version_first() {
+            let options = ParseOptions { legacy_version_first: true, ..ParseOptions::default() };
+            let ms = MatchSpec::parse_with("1.20 numpy", &options).unwrap();
+            assert_eq!(ms, "numpy==1.20".parse::<MatchSpec>().unwrap());
+            assert_eq!(ms.raw(), Some("1.20 numpy"));
+
+            let ms = MatchSpec::parse_with("1.21.0 numpy py39h_0", &options).unwrap();
+            assert_eq!(ms, "numpy 1.21.0 py39h_0".parse::<MatchSpec>().unwrap());
+
+            // Normal specs are unaffected, even ones with a number for a name
+            let ms = MatchSpec::parse_with("numpy 1.20", &options).unwrap();
+            assert_eq!(ms, "numpy==1.20".parse::<MatchSpec>().unwrap());
+            assert_eq!(MatchSpec::parse_with("7za", &options).unwrap().package, "7za");
+
+            // A name that looks like a version is kept as the name when a version follows it
+            let ms = MatchSpec::parse_with("7za 9.20", &options).unwrap();
+            assert_eq!(ms, "7za==9.20".parse::<MatchSpec>().unwrap());
+            let ms = MatchSpec::parse_with("4ti2 1.6.9", &options).unwrap();
+            assert_eq!(ms.package, "4ti2");
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.6.9"))));
+            let ms = MatchSpec::parse_with("4ti2 1.6.9 h1234_0", &options).unwrap();
+            assert_eq!(ms, "4ti2 1.6.9 h1234_0".parse::<MatchSpec>().unwrap());
+
+            // It's opt in
+            assert_ne!("1.20 numpy".parse::<MatchSpec>().ok().map(|ms| ms.package), Some("numpy".to_string()));
+        }
+
+        #[test]
+        fn plus_build_number() {
+            let options = ParseOptions { plus_build_number: true, ..ParseOptions::default() };
+            let ms = MatchSpec::parse_with("pkg==1.0+3", &options).unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.0"))));
+            assert_eq!(ms.build_number, Some(CompoundSelector::from(("=", "3"))));
+            assert_eq!(ms, "pkg==1.0[build_number=3]".parse::<MatchSpec>().unwrap());
+            assert_eq!(ms.raw(), Some("pkg==1.0+3"));
+            assert!(ms.is_match(&PackageCandidate {
+                build_number: Some(3),
+                ..test_candidate("pkg", "1.0")
+            }));
+
+            // Only a number after an `==` version, and the build_number key wins
+            let ms = MatchSpec::parse_with("pkg==1.0+cpu", &options).unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.0+cpu"))));
+            assert_eq!(ms.build_number, None);
+            let ms = MatchSpec::parse_with("pkg>=1.0+3", &options).unwrap();
+            assert_eq!(ms.build_number, None);
+            let ms = MatchSpec::parse_with("pkg==1.0+3[build_number=4]", &options).unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.0+3"))));
+            assert_eq!(ms.build_number, Some(CompoundSelector::from(("=", "4"))));
+
+            // It's opt in
+            let ms: MatchSpec = "pkg==1.0+3".parse().unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.0+3"))));
+            assert_eq!(ms.build_number, None);
+        }
+
+        #[test]
+        fn deserialize() {
+            #[derive(Debug, serde::Deserialize)]
+            struct Config {
+                name: String,
+                spec: MatchSpec,
+                pins: Vec<MatchSpec>,
+            }
+
+            let config: Config = serde_json::from_str(
+                r#"{"name": "env", "spec": "numpy>=1.20[build=py39*]", "pins": ["python 3.9.*", "conda-forge::zlib"]}"#,
+            )
+            .unwrap();
+            assert_eq!(config.name, "env");
+            assert_eq!(config.spec, "numpy>=1.20[build=py39*]".parse::<MatchSpec>().unwrap());
+            assert_eq!(config.pins[1].channel, Some("conda-forge".to_string()));
+
+            // The parse error is passed through
+            let err = serde_json::from_str::<Config>(r#"{"name": "env", "spec": "python=wrong", "pins": []}"#).unwrap_err();
+            assert!(err.to_string().contains("python=wrong"), "{}", err);
+            assert!(serde_json::from_str::<Config>(r#"{"name": "env", "spec": 1, "pins": []}"#).is_err());
+        }
+
+        #[test]
+        fn try_into() {
+            fn parse_all<T: TryInto<MatchSpec, Error = MatchSpecError>>(specs: Vec<T>) -> Result<Vec<MatchSpec>, MatchSpecError> {
+                specs.into_iter().map(TryInto::try_into).collect()
+            }
+
+            let specs = parse_all(vec!["numpy>=1.20", "python 3.10.*"]).unwrap();
+            assert_eq!(specs[0], "numpy>=1.20".parse::<MatchSpec>().unwrap());
+            assert_eq!(specs[1].package, "python");
+
+            let specs = parse_all(vec!["conda-forge::zlib".to_string()]).unwrap();
+            assert_eq!(specs[0].channel, Some("conda-forge".to_string()));
+
+            let ms: Result<MatchSpec, MatchSpecError> = "python=wrong".try_into();
+            assert!(ms.is_err());
+            assert!(MatchSpec::try_from(String::from("conda-forge::")).is_err());
+        }
+
+        #[test]
+        fn raw_input() {
+            let ms: MatchSpec = "conda-forge::numpy>=1.20  # pinned\n".parse().unwrap();
+            assert_eq!(ms.raw(), Some("conda-forge::numpy>=1.20  # pinned"));
+
+            // The raw string doesn't affect equality
+            let other: MatchSpec = "conda-forge::numpy>=1.20".parse().unwrap();
+            assert_eq!(ms, other);
+
+            // Derived MatchSpecs no longer look like the original input
+            assert_eq!(ms.rename_package("scipy").raw(), None);
+            assert_eq!(MatchSpec::from(MatchSpec::parse_borrowed("numpy").unwrap()).raw(), None);
+        }
+
+        #[test]
+        fn not_keyword() {
+            let options = ParseOptions { not_keyword: true, ..ParseOptions::default() };
+            let ms = MatchSpec::parse_with("numpy not 1.19.*", &options).unwrap();
+            assert_eq!(ms, "numpy!=1.19.*".parse::<MatchSpec>().unwrap());
+            assert!(!ms.is_package_version_match("numpy", "1.19.3"));
+            assert!(ms.is_package_version_match("numpy", "1.20.0"));
+
+            // Everything else still parses the same way
+            let ms = MatchSpec::parse_with("numpy 1.19.3 py39h_0", &options).unwrap();
+            assert_eq!(ms.build, Some("py39h_0".to_string()));
+
+            // It's opt in
+            assert!("numpy not 1.19.*".parse::<MatchSpec>().is_err());
+        }
+
+        #[test]
+        fn trailing_comments() {
+            let ms: MatchSpec = "numpy>=1.20  # pinned".parse().unwrap();
+            assert_eq!(ms, "numpy>=1.20".parse().unwrap());
+
+            let ms: MatchSpec = "numpy 1.21.0 py39h_0 # from the lockfile".parse().unwrap();
+            assert_eq!(ms, "numpy 1.21.0 py39h_0".parse().unwrap());
+
+            let ms: MatchSpec = "numpy#no space".parse().unwrap();
+            assert_eq!(ms, "numpy".parse().unwrap());
+
+            // A # inside of quotes isn't a comment
+            assert_eq!(strip_comment("numpy[license='GPL#2'] # comment"), "numpy[license='GPL#2']");
+            assert_eq!(strip_comment("numpy[license=\"GPL#2\"]"), "numpy[license=\"GPL#2\"]");
+
+            assert!("# only a comment".parse::<MatchSpec>().is_err());
+        }
+
+        #[test]
+        fn compound_selector_single() {
+            let cs: CompoundSelector<String> = ">=1.2".parse().unwrap();
+            assert_eq!(cs, CompoundSelector::from((">=", "1.2")));
+
+            let cs: CompoundSelector<String> = " == 2.9.1".parse().unwrap();
+            assert_eq!(cs, CompoundSelector::from(("==", "2.9.1")));
+        }
+
+        #[test]
+        fn compound_selector_and() {
+            let cs: CompoundSelector<String> = ">=1.2,<2.0".parse().unwrap();
+            assert_eq!(cs, CompoundSelector::from((">=", "1.2", ",", "<", "2.0")));
+        }
+
+        #[test]
+        fn compound_selector_or() {
+            let cs: CompoundSelector<String> = "<1.0|>2.0".parse().unwrap();
+            assert_eq!(cs, CompoundSelector::from(("<", "1.0", "|", ">", "2.0")));
+
+            // `||` is the same joiner
+            let cs: CompoundSelector<String> = ">1.0||<0.5".parse().unwrap();
+            assert_eq!(cs, CompoundSelector::from((">", "1.0", "|", "<", "0.5")));
+            let ms: MatchSpec = "numpy >1.0 || <0.5".parse().unwrap();
+            assert_eq!(ms.version, Some(cs));
+        }
+
+        #[test]
+        fn compound_selector_errors() {
+            assert!("".parse::<CompoundSelector<String>>().is_err());
+            assert!("numpy>=1.2".parse::<CompoundSelector<String>>().is_err());
+            assert_eq!(
+                ">=1.2 trailing".parse::<CompoundSelector<String>>(),
+                Err(MatchSpecError {
+                    message: " trailing".to_string()
+                })
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod lock_lines {
+        use crate::matchspec::*;
+
+        #[test]
+        fn conda_lock_pins() {
+            let ms = MatchSpec::parse_lock_line("  - conda-forge::python=3.11.4=hab00c5b_0_cpython").unwrap();
+            assert_eq!(ms.channel.as_deref(), Some("conda-forge"));
+            assert_eq!(ms.package, "python");
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "3.11.4"))));
+            assert_eq!(ms.build.as_deref(), Some("hab00c5b_0_cpython"));
+
+            let ms = MatchSpec::parse_lock_line("- _libgcc_mutex=0.1=conda_forge").unwrap();
+            assert_eq!(ms.package, "_libgcc_mutex");
+            assert_eq!(ms.channel, None);
+            assert_eq!(ms.build.as_deref(), Some("conda_forge"));
+
+            let ms = MatchSpec::parse_lock_line("conda-forge/linux-64::libzlib=1.2.13=hd590300_5").unwrap();
+            assert_eq!(ms.subdir.as_deref(), Some("linux-64"));
+            assert_eq!(ms.raw(), Some("conda-forge/linux-64::libzlib=1.2.13=hd590300_5"));
+
+            let ms = MatchSpec::parse_lock_line("ca-certificates=2023.7.22=hbcca054_0  # from conda-forge").unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "2023.7.22"))));
+
+            let pc = PackageCandidate {
+                build: Some("hab00c5b_0_cpython".to_string()),
+                ..test_candidate("python", "3.11.4")
             };
+            assert!(MatchSpec::parse_lock_line("python=3.11.4=hab00c5b_0_cpython").unwrap().is_match(&pc));
+            assert!(!MatchSpec::parse_lock_line("python=3.11.4=hab00c5b_1_cpython").unwrap().is_match(&pc));
+        }
 
-            assert!(or.is_match("3.0.0"));
-            assert!(or.is_match("0.1.1"));
-            assert!(!or.is_match("1.2.1"));
-            assert!(!or.is_match("1.1.1"));
-            assert!(!or.is_match("1.1.7"));
+        #[test]
+        fn not_pinned() {
+            for line in [
+                "numpy",
+                "numpy=1.21.0",
+                "numpy>=1.21",
+                "numpy==1.21.0=py39h_0",
+                "numpy=1.21.*=py39h_0",
+                "numpy=1.21.0=py39*",
+                "numpy=1.21.0=py39h_0=extra",
+                "numpy 1.21.0 py39h_0",
+                "numpy=1.21.0=py39h_0[md5=abc]",
+                "",
+            ] {
+                assert!(MatchSpec::parse_lock_line(line).is_err(), "{}", line);
+            }
+            assert_eq!(
+                MatchSpec::parse_lock_line("- numpy=1.21.0").unwrap_err().message,
+                "'numpy=1.21.0' is not a pinned name=version=build line"
+            );
         }
+    }
+
+    #[cfg(test)]
+    mod rendering {
+        use crate::matchspec::*;
 
         #[test]
-        fn test_version_compare() {
-            let ms: MatchSpec = "python>3.6".parse().unwrap();
-            assert!(!ms.is_package_version_match("python", "3.5"));
-            assert!(ms.is_package_version_match("python", "3.8"));
-            assert!(ms.is_package_version_match("python", "3.9"));
-            assert!(ms.is_package_version_match("python", "3.10"));
+        fn display_round_trips() {
+            let specs = [
+                "numpy",
+                "numpy>=1.20",
+                "numpy>=1.20,<2.0",
+                "numpy<1.0|>2.0",
+                "conda-forge::numpy>=1.20",
+                "conda-forge/linux-64::numpy==1.21.0[build=py39h_0]",
+                "main/linux-64:UNUSED:tensorflow>2.9.1,<3.0.0[license=GPL]",
+                "numpy[build_number='>=2,<5',subdir=linux-64]",
+                "tensorflow*[exclude=tensorflow-gpu]",
+                "numpy[license='BSD 3-Clause',timestamp='>=2021-01-01T12:00:00Z']",
+            ];
+
+            for spec in specs {
+                let ms: MatchSpec = spec.parse().unwrap();
+                assert_eq!(ms.to_string(), spec);
+                assert_eq!(ms.to_string().parse::<MatchSpec>().unwrap(), ms);
+            }
+        }
+
+        #[test]
+        fn debug_output() {
+            let ms: MatchSpec = "conda-forge::numpy>=1.20[build=py39*, license=BSD]".parse().unwrap();
+            let debug = format!("{:?}", ms);
+            assert_eq!(debug, r#"MatchSpec("conda-forge::numpy>=1.20[build=py39*,license=BSD]")"#);
+
+            // It nests inside other Debug output too
+            let specs = vec![ms.clone(), "python".parse().unwrap()];
+            assert_eq!(format!("{:?}", specs), format!(r#"[{}, MatchSpec("python")]"#, debug));
+
+            let pretty = format!("{:#?}", ms);
+            assert!(pretty.contains(r#"channel: Some(
+        "conda-forge",
+    )"#), "{}", pretty);
+            assert!(pretty.contains("literal_package: false"));
+        }
+
+        #[test]
+        fn stable_keys() {
+            let key = |spec: &str| spec.parse::<MatchSpec>().unwrap().stable_key();
+
+            // Equivalent specs written differently have the same key
+            assert_eq!(
+                key("conda-forge::numpy>=1.20.0,<2[license=BSD, md5=abc, build=py39*]"),
+                key("conda-forge::numpy<2.0,>=1.20[build=py39*, md5=abc, license=BSD]")
+            );
+            assert_eq!(key("numpy 1.21.0"), key("numpy==1.21"));
+            assert_eq!(key("numpy=1.21"), key("numpy==1.21.0"));
+            assert_eq!(key("numpy<1.0|>2.0"), key("numpy>2|<1"));
+            assert_eq!(key("numpy>=1.0,>=1.2"), key("numpy>=1.2"));
+            assert_eq!(key("pkg>=0!1.0"), key("pkg>=1"));
+            assert_eq!(key("numpy"), "numpy[]");
+
+            // Different specs still have different keys
+            assert_ne!(key("numpy>=1.20"), key("numpy>1.20"));
+            assert_ne!(key("numpy==1.2"), key("numpy==1.20"));
+            assert_ne!(key("numpy[license=BSD]"), key("numpy[license=MIT]"));
+            assert_ne!(key("conda-forge::numpy"), key("numpy"));
+            assert_ne!(key("numpy==1.0+cpu"), key("numpy==1+cpu"));
+
+            // A literal name isn't a glob, so it can't share the glob's key
+            let options = ParseOptions { literal_package: true, ..ParseOptions::default() };
+            let literal = MatchSpec::parse_with("numpy*", &options).unwrap();
+            assert_ne!(literal, "numpy*".parse::<MatchSpec>().unwrap());
+            assert_ne!(literal.stable_key(), key("numpy*"));
+            assert_eq!(literal.stable_key(), "numpy*[literal]");
+        }
+
+        #[test]
+        fn escape_values() {
+            // Values that can be written bare are left alone
+            for value in ["py39h_0", "linux-64", "1.2.3", "*mkl*", "GPL"] {
+                assert_eq!(escape_value(value).unwrap(), value);
+            }
+
+            assert_eq!(escape_value("BSD 3-Clause").unwrap(), "'BSD 3-Clause'");
+            assert_eq!(escape_value("Apache, 2.0").unwrap(), "'Apache, 2.0'");
+            assert_eq!(escape_value("a[b]").unwrap(), "'a[b]'");
+            assert_eq!(escape_value("it's").unwrap(), "\"it's\"");
+            assert_eq!(escape_value("it's \"x\""), None);
+
+            // Display uses it for the key values, so they parse back the same
+            let ms: MatchSpec = "numpy[license=\"it's, BSD\", md5=abc123]".parse().unwrap();
+            assert_eq!(ms.to_string(), "numpy[license=\"it's, BSD\",md5=abc123]");
+            assert_eq!(ms.to_string().parse::<MatchSpec>().unwrap(), ms);
+
+            // A value with both quotes can't be written, instead of being dropped on the way back
+            let mut ms: MatchSpec = "pkg".parse().unwrap();
+            let license = CompoundSelector::Single { selector: Selector::EqualTo, version: "it's \"x\"".to_string() };
+            ms.key_value_pairs.push(("license".to_string(), license));
+            assert!(std::fmt::Write::write_fmt(&mut String::new(), format_args!("{}", ms)).is_err());
+            assert_eq!(ms.to_environment_yml_line(), None);
+            assert!(format!("{:?}", ms).contains("it's"));
+            assert!(ms.stable_key().contains("license=\"it's \\\"x\\\"\""));
+        }
+
+        #[test]
+        fn environment_yml_simple() {
+            let line = |spec: &str| spec.parse::<MatchSpec>().unwrap().to_environment_yml_line().unwrap();
+
+            assert_eq!(line("numpy"), "numpy");
+            assert_eq!(line("numpy>=1.20"), "numpy>=1.20");
+            assert_eq!(line("numpy >=1.20, <2.0"), "numpy>=1.20,<2.0");
+            assert_eq!(line("numpy 1.21.0"), "numpy==1.21.0");
+            assert_eq!(line("numpy 1.21.0 py39h_0"), "numpy=1.21.0=py39h_0");
+            assert_eq!(line("numpy[build=py39h_0]"), "numpy[build=py39h_0]");
+            assert_eq!(line("numpy>=1.20[build=py39h_0]"), "numpy>=1.20[build=py39h_0]");
+        }
+
+        #[test]
+        fn pip_requirement() {
+            let pip = |spec: &str| spec.parse::<MatchSpec>().unwrap().to_pip_requirement();
+
+            assert_eq!(pip("numpy"), Some("numpy".to_string()));
+            assert_eq!(pip("numpy *"), Some("numpy".to_string()));
+            assert_eq!(pip("numpy 1.21.0"), Some("numpy==1.21.0".to_string()));
+            assert_eq!(pip("numpy >=1.20, <2.0"), Some("numpy>=1.20,<2.0".to_string()));
+            assert_eq!(pip("numpy===1.21.0+local"), Some("numpy===1.21.0+local".to_string()));
+
+            assert_eq!(pip("conda-forge::numpy"), None);
+            assert_eq!(pip("numpy 1.21.0 py39h_0"), None);
+            assert_eq!(pip("numpy[build_number=2]"), None);
+            assert_eq!(pip("numpy[subdir=linux-64]"), None);
+            assert_eq!(pip("numpy<1.0|>2.0"), None);
+            assert_eq!(pip("tensorflow*"), None);
+        }
+
+        #[test]
+        fn environment_yml_channel() {
+            let line = |spec: &str| spec.parse::<MatchSpec>().unwrap().to_environment_yml_line().unwrap();
+
+            assert_eq!(line("conda-forge::numpy>=1.20"), "conda-forge::numpy>=1.20");
+            assert_eq!(
+                line("conda-forge/linux-64::numpy>=1.20"),
+                "conda-forge/linux-64::numpy>=1.20"
+            );
+            assert_eq!(
+                line("numpy>=1.20[channel=conda-forge]"),
+                "conda-forge::numpy>=1.20"
+            );
+        }
+    }
+
+    #[cfg(test)]
+    mod subdirs {
+        use crate::matchspec::*;
+
+        const SUBDIRS: [&str; 12] = [
+            "noarch",
+            "linux-64",
+            "linux-32",
+            "linux-aarch64",
+            "linux-armv7l",
+            "linux-ppc64le",
+            "linux-s390x",
+            "osx-64",
+            "osx-arm64",
+            "win-64",
+            "win-32",
+            "win-arm64",
+        ];
+
+        #[test]
+        fn standard_subdirs() {
+            for subdir in SUBDIRS {
+                let candidate = PackageCandidate {
+                    build: Some("py39h_0".to_string()),
+                    subdir: Some(subdir.to_string()),
+                    ..test_candidate("numpy", "1.26.4")
+                };
+
+                for spec in [
+                    format!("conda-forge/{}::numpy", subdir),
+                    format!("numpy[subdir={}]", subdir),
+                    format!("conda-forge/{}::numpy==1.26.4[build=py39h_0]", subdir),
+                ] {
+                    let ms: MatchSpec = spec.parse().unwrap();
+                    assert_eq!(ms.subdir.as_deref(), Some(subdir), "{}", spec);
+                    assert_eq!(ms.to_string(), spec);
+                    assert!(ms.is_match(&candidate), "{}", spec);
+                }
+            }
+        }
+
+        #[test]
+        fn path_like_builds() {
+            let ms: MatchSpec = "numpy[subdir=win-64,build='py39\\h_0']".parse().unwrap();
+            assert_eq!(ms.subdir.as_deref(), Some("win-64"));
+            assert_eq!(ms.build.as_deref(), Some("py39\\h_0"));
+
+            // The build stays quoted so it parses back the same
+            assert_eq!(ms.to_string(), "numpy[build='py39\\h_0',subdir=win-64]");
+            assert_eq!(ms.to_string().parse::<MatchSpec>().unwrap(), ms);
+            assert_eq!(ms.to_environment_yml_line().unwrap(), "numpy[build='py39\\h_0',subdir=win-64]");
+        }
+    }
+
+    #[cfg(test)]
+    mod filenames {
+        use crate::matchspec::*;
+
+        #[test]
+        fn matches_filename() {
+            let ms: MatchSpec = "numpy>=1.20,<2".parse().unwrap();
+            assert!(ms.matches_filename("numpy-1.21.0-py39h_0.tar.bz2"));
+            assert!(ms.matches_filename("numpy-1.26.4-py312h2809609_0.conda"));
+            assert!(!ms.matches_filename("numpy-2.0.0-py312h2809609_0.conda"));
+            assert!(!ms.matches_filename("numpy-base-1.21.0-py39h_0.conda"));
+            assert!(!ms.matches_filename("numpy-1.21.0.zip"));
+
+            let ms: MatchSpec = "ca-certificates>=2023[build=h06a*,extension=conda]".parse().unwrap();
+            assert!(ms.matches_filename("ca-certificates-2023.01.10-h06a4308_0.conda"));
+            assert!(!ms.matches_filename("ca-certificates-2023.01.10-h06a4308_0.tar.bz2"));
+            assert!(!ms.matches_filename("ca-certificates-2023.01.10-h1234567_0.conda"));
+        }
+    }
+
+    #[cfg(test)]
+    mod family {
+        use crate::matchspec::*;
+
+        fn same_family(a: &str, b: &str) -> bool {
+            a.parse::<MatchSpec>().unwrap().same_family(&b.parse().unwrap())
+        }
+
+        #[test]
+        fn variants() {
+            assert!(same_family("tensorflow-gpu", "tensorflow-cpu"));
+            assert!(same_family("tensorflow-gpu>=2.0", "tensorflow 2.9.1 mkl_py39hb9fcb14_0"));
+            assert!(same_family("conda-forge::numpy-base", "conda-forge::numpy<2"));
+            assert_eq!("tensorflow-gpu".parse::<MatchSpec>().unwrap().package_family(), "tensorflow");
+
+            assert!(!same_family("numpy", "scipy"));
+            assert!(!same_family("conda-forge::tensorflow-gpu", "defaults::tensorflow-cpu"));
+            assert!(!same_family("conda-forge::numpy", "numpy"));
+        }
+    }
+
+    #[cfg(test)]
+    mod query_params {
+        use crate::matchspec::*;
+
+        fn params(spec: &str) -> Vec<(String, String)> {
+            spec.parse::<MatchSpec>().unwrap().as_query_params()
+        }
+
+        fn expected(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        }
+
+        #[test]
+        fn full_spec() {
+            assert_eq!(
+                params("conda-forge/linux-64::numpy>=1.20,<2.0[build=py39*]"),
+                expected(&[
+                    ("package", "numpy"),
+                    ("version", ">=1.20,<2.0"),
+                    ("build", "py39*"),
+                    ("subdir", "linux-64"),
+                    ("channel", "conda-forge"),
+                ])
+            );
+            assert_eq!(
+                params("conda-forge,defaults::numpy"),
+                expected(&[("package", "numpy"), ("channel", "conda-forge,defaults")])
+            );
+            assert_eq!(params("numpy"), expected(&[("package", "numpy")]));
+        }
+    }
+
+    #[cfg(test)]
+    mod environment {
+        use crate::matchspec::*;
+
+        fn environment() -> Vec<PackageCandidate> {
+            vec![
+                PackageCandidate::from(r#"{"name": "python", "version": "3.10.4", "build_number": 1}"#),
+                PackageCandidate::from(r#"{"name": "numpy", "version": "1.23.5"}"#),
+                PackageCandidate::from(r#"{"name": "openssl", "version": "1.1.1t"}"#),
+            ]
+        }
+
+        #[test]
+        fn satisfied_environment() {
+            let specs: Vec<MatchSpec> = vec![
+                "python>=3.9".parse().unwrap(),
+                "numpy>1.20,<2.0".parse().unwrap(),
+                "openssl".parse().unwrap(),
+            ];
+
+            assert_eq!(environment_satisfies(&specs, &environment()), Ok(()));
+        }
+
+        #[test]
+        fn missing_package() {
+            let specs: Vec<MatchSpec> = vec![
+                "python>=3.9".parse().unwrap(),
+                "pytorch>1.10".parse().unwrap(),
+                "numpy<1.0".parse().unwrap(),
+            ];
+
+            assert_eq!(
+                environment_satisfies(&specs, &environment()),
+                Err(vec![
+                    "pytorch>1.10".parse().unwrap(),
+                    "numpy<1.0".parse().unwrap(),
+                ])
+            );
+        }
+
+        #[test]
+        fn grouped_candidates() {
+            let candidates: Vec<PackageCandidate> = [
+                r#"{"name": "python", "version": "3.10.4"}"#,
+                r#"{"name": "numpy", "version": "1.23.5"}"#,
+                r#"{"name": "python", "version": "3.8.16"}"#,
+                r#"{"name": "numpy", "version": "1.26.4"}"#,
+                r#"{"name": "numpy-base", "version": "1.26.4"}"#,
+                r#"{"name": "openssl", "version": "1.1.1t"}"#,
+            ]
+            .into_iter()
+            .map(PackageCandidate::from)
+            .collect();
+            let specs: Vec<MatchSpec> = ["python>=3.9", "numpy*>=1.24", "numpy-base", "pytorch"]
+                .iter()
+                .map(|ms| ms.parse().unwrap())
+                .collect();
+
+            let groups = group_and_filter(&candidates, &specs);
+            let versions = |name: &str| -> Vec<&str> {
+                groups[name].iter().map(|pc| pc.version.as_deref().unwrap()).collect()
+            };
+
+            // openssl and pytorch have nothing that matches, so they have no group
+            assert_eq!(groups.len(), 3);
+            assert_eq!(versions("python"), vec!["3.10.4"]);
+            assert_eq!(versions("numpy"), vec!["1.26.4"]);
+            // Matched by two specs but only listed once
+            assert_eq!(versions("numpy-base"), vec!["1.26.4"]);
+
+            assert!(group_and_filter(&candidates, &[]).is_empty());
         }
     }
 }
@@ -0,0 +1,204 @@
+use crate::error::MatchSpecError;
+use crate::matchspec::{strip_comment, MatchSpec};
+use crate::package_candidate::PackageCandidate;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// An OR of whole MatchSpecs, for when any one of several different packages will do:
+/// ```text
+/// numpy>=1.20 || scipy>=1.0
+/// ```
+/// Unlike the `|` joiner inside a MatchSpec, each side can be a completely different package.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatchSpecGroup {
+    pub matchspecs: Vec<MatchSpec>,
+}
+
+impl From<Vec<MatchSpec>> for MatchSpecGroup {
+    fn from(matchspecs: Vec<MatchSpec>) -> Self {
+        MatchSpecGroup { matchspecs }
+    }
+}
+
+/// Parses a `||` separated list of MatchSpecs
+/// ```
+/// use rust_matchspec::matchspec_group::MatchSpecGroup;
+///
+/// let group: MatchSpecGroup = "numpy>=1.20 || scipy>=1.0".parse().unwrap();
+/// assert_eq!(group.matchspecs.len(), 2);
+/// ```
+impl FromStr for MatchSpecGroup {
+    type Err = MatchSpecError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `||` can also join two versions inside a single MatchSpec, like `numpy>2.0||<1.0`. Those
+        // sides start with a selector rather than a package name, so join them back up.
+        let mut parts: Vec<String> = Vec::new();
+        for part in split_alternatives(strip_comment(s)) {
+            match parts.last_mut() {
+                Some(last) if part.trim_start().starts_with(['<', '>', '=', '!', '~']) => {
+                    last.push_str("||");
+                    last.push_str(part);
+                }
+                _ => parts.push(part.to_string()),
+            }
+        }
+
+        let matchspecs = parts
+            .iter()
+            .map(|ms| ms.trim().parse())
+            .collect::<Result<Vec<MatchSpec>, MatchSpecError>>()?;
+
+        Ok(MatchSpecGroup { matchspecs })
+    }
+}
+
+/// Splits on the `||` that are outside of quoted values and brackets, so a value like
+/// `[license='GPL||MIT']` stays in its MatchSpec
+fn split_alternatives(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut brackets = 0usize;
+    let mut start = 0;
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => (),
+            ('\'' | '"', None) => quote = Some(c),
+            ('[', None) => brackets += 1,
+            (']', None) => brackets = brackets.saturating_sub(1),
+            ('|', None) if brackets == 0 && matches!(chars.peek(), Some((_, '|'))) => {
+                chars.next();
+                parts.push(&s[start..i]);
+                start = i + 2;
+            }
+            _ => (),
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Renders the group the way it's parsed, with ` || ` between the MatchSpecs
+impl Display for MatchSpecGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let matchspecs: Vec<String> = self.matchspecs.iter().map(MatchSpec::to_string).collect();
+        write!(f, "{}", matchspecs.join(" || "))
+    }
+}
+
+impl MatchSpecGroup {
+    /// Returns true if any of the MatchSpecs in this group match the candidate
+    pub fn is_match_any(&self, pc: &PackageCandidate) -> bool {
+        self.matchspecs.iter().any(|ms| ms.is_match(pc))
+    }
+}
+
+impl MatchSpec {
+    /// Combines two MatchSpecs into a group that matches anything either of them matches. Unlike
+    /// an `|` between versions, the two sides can be for different packages.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    ///
+    /// let a: MatchSpec = "numpy>=1.20".parse().unwrap();
+    /// let b: MatchSpec = "scipy>=1.0".parse().unwrap();
+    /// assert_eq!(a.union(&b).to_string(), "numpy>=1.20 || scipy>=1.0");
+    /// ```
+    pub fn union(&self, other: &MatchSpec) -> MatchSpecGroup {
+        MatchSpecGroup::from(vec![self.clone(), other.clone()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod matchspec_group {
+        use crate::matchspec::*;
+        use crate::matchspec_group::*;
+        use crate::package_candidate::test_candidate;
+
+        #[test]
+        fn parse_group() {
+            let group: MatchSpecGroup = "numpy>=1.0||scipy>=1.0".parse().unwrap();
+            assert_eq!(
+                group,
+                MatchSpecGroup::from(vec![
+                    "numpy>=1.0".parse::<MatchSpec>().unwrap(),
+                    "scipy>=1.0".parse::<MatchSpec>().unwrap(),
+                ])
+            );
+
+            // A single spec is a group of one
+            let group: MatchSpecGroup = "numpy>=1.0".parse().unwrap();
+            assert_eq!(group.matchspecs.len(), 1);
+
+            assert!("numpy>=1.0 || ".parse::<MatchSpecGroup>().is_err());
+
+            // A `||` between versions stays inside its MatchSpec
+            let group: MatchSpecGroup = "numpy>2.0||<1.0 || scipy>=1.0".parse().unwrap();
+            assert_eq!(
+                group,
+                MatchSpecGroup::from(vec![
+                    "numpy>2.0|<1.0".parse::<MatchSpec>().unwrap(),
+                    "scipy>=1.0".parse::<MatchSpec>().unwrap(),
+                ])
+            );
+
+            // A `||` in a quoted value or a comment doesn't start another MatchSpec
+            let group: MatchSpecGroup = "numpy[license='GPL||MIT'] || scipy".parse().unwrap();
+            assert_eq!(
+                group,
+                MatchSpecGroup::from(vec![
+                    "numpy[license='GPL||MIT']".parse::<MatchSpec>().unwrap(),
+                    "scipy".parse::<MatchSpec>().unwrap(),
+                ])
+            );
+            assert_eq!(
+                group.matchspecs[0].key_values().get("license"),
+                Some(&&CompoundSelector::from(("=", "GPL||MIT")))
+            );
+            let group: MatchSpecGroup = "numpy # a || b".parse().unwrap();
+            assert_eq!(
+                group,
+                MatchSpecGroup::from(vec!["numpy".parse::<MatchSpec>().unwrap()])
+            );
+        }
+
+        #[test]
+        fn match_any() {
+            let group: MatchSpecGroup = "numpy>=1.0 || scipy>=1.0".parse().unwrap();
+
+            let numpy = PackageCandidate::from(r#"{"name": "numpy", "version": "1.23.5"}"#);
+            let old_numpy = PackageCandidate::from(r#"{"name": "numpy", "version": "0.9.8"}"#);
+            let scipy = PackageCandidate::from(r#"{"name": "scipy", "version": "1.10.0"}"#);
+            let pandas = PackageCandidate::from(r#"{"name": "pandas", "version": "1.5.3"}"#);
+
+            assert!(group.is_match_any(&numpy));
+            assert!(group.is_match_any(&scipy));
+            assert!(!group.is_match_any(&old_numpy));
+            assert!(!group.is_match_any(&pandas));
+
+            assert!(!MatchSpecGroup::default().is_match_any(&numpy));
+        }
+
+        #[test]
+        fn union() {
+            let a: MatchSpec = "numpy>=1.20".parse().unwrap();
+            let b: MatchSpec = "numpy<1.0".parse().unwrap();
+            let c: MatchSpec = "scipy".parse().unwrap();
+
+            let union = a.union(&b);
+            assert_eq!(union, MatchSpecGroup::from(vec![a.clone(), b.clone()]));
+            assert_eq!(union.to_string().parse::<MatchSpecGroup>().unwrap(), union);
+
+            assert!(union.is_match_any(&test_candidate("numpy", "1.26.4")));
+            assert!(union.is_match_any(&test_candidate("numpy", "0.9.8")));
+            assert!(!union.is_match_any(&test_candidate("numpy", "1.10.0")));
+
+            let union = a.union(&c);
+            assert!(union.is_match_any(&test_candidate("numpy", "1.26.4")));
+            assert!(union.is_match_any(&test_candidate("scipy", "0.1")));
+            assert!(!union.is_match_any(&test_candidate("pandas", "2.0")));
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::matchspec::*;
+
+/// A MatchSpec that borrows all of its fields from the string it was parsed from. Parsing into a
+/// MatchSpecRef avoids allocating a `String` per field, which adds up when specs are only parsed
+/// to do a quick match and then thrown away. Use `MatchSpec::parse_borrowed` to create one, and
+/// `MatchSpec::from` if an owned copy is needed.
+/// ```
+/// use rust_matchspec::MatchSpec;
+///
+/// let ms = MatchSpec::parse_borrowed("conda-forge::numpy>=1.20").unwrap();
+/// assert_eq!(ms.channel, Some("conda-forge"));
+/// assert!(ms.is_package_version_match("numpy", "1.21.0"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchSpecRef<'a> {
+    /// The channel as written, so a channel list like `conda-forge,defaults` is kept whole
+    pub channel: Option<&'a str>,
+    pub subdir: Option<&'a str>,
+    pub namespace: Option<&'a str>,
+    pub package: &'a str,
+    pub version: Option<CompoundSelector<&'a str>>,
+    pub build: Option<&'a str>,
+    pub build_number: Option<CompoundSelector<&'a str>>,
+    pub key_value_pairs: Vec<(&'a str, CompoundSelector<&'a str>)>,
+    pub exclude: Vec<&'a str>,
+}
+
+impl<'a> From<(&'a str, Option<&'a str>, Option<&'a str>)> for MatchSpecRef<'a> {
+    fn from((package, version, build): (&'a str, Option<&'a str>, Option<&'a str>)) -> Self {
+        MatchSpecRef {
+            channel: None,
+            subdir: None,
+            namespace: None,
+            package,
+            version: version.map(|version| CompoundSelector::Single {
+                selector: Selector::EqualTo,
+                version,
+            }),
+            build,
+            build_number: None,
+            key_value_pairs: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl<'a>
+From<(
+    Option<&'a str>,
+    Option<&'a str>,
+    Option<&'a str>,
+    &'a str,
+    Option<CompoundSelector<&'a str>>,
+    Option<Vec<(&'a str, CompoundSelector<&'a str>)>>,
+)> for MatchSpecRef<'a>
+{
+    fn from(
+        (channel, subdir, ns, package, cs, keys): (
+            Option<&'a str>,
+            Option<&'a str>,
+            Option<&'a str>,
+            &'a str,
+            Option<CompoundSelector<&'a str>>,
+            Option<Vec<(&'a str, CompoundSelector<&'a str>)>>,
+        ),
+    ) -> Self {
+        // Create the initial struct based on the parsed tuple
+        let mut ms = MatchSpecRef {
+            channel,
+            subdir,
+            namespace: ns.filter(|a| !a.is_empty()),
+            package,
+            version: cs,
+            build: None,
+            build_number: None,
+            key_value_pairs: keys.unwrap_or_default(),
+            exclude: Vec::new(),
+        };
+
+        // Lets set the final attributes based on the key value pairs
+        // Currently we only support EqualTo relations, but maybe in the future we can fix that.
+        // Key names are case insensitive, so `[Subdir=linux-64]` works too.
+        for (key, compound_selector) in &ms.key_value_pairs {
+            match (key.to_ascii_lowercase().as_str(), compound_selector) {
+                ("build", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.build = Some(version),
+                ("channel", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.channel = Some(version),
+                ("subdir", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.subdir = Some(version),
+                ("namespace", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.namespace = Some(version),
+                // Repeated build_number constraints are combined so they are all enforced
+                ("build_number", CompoundSelector::Single { selector, version }) => {
+                    ms.build_number = match ms.build_number.take() {
+                        Some(CompoundSelector::Single { selector: first_selector, version: first_version }) => Some(CompoundSelector::And {
+                            first_selector,
+                            first_version,
+                            second_selector: selector.clone(),
+                            second_version: version,
+                        }),
+                        _ => Some(compound_selector.clone()),
+                    }
+                }
+                ("build_number", _) => ms.build_number = Some(compound_selector.clone()),
+                // A quoted `[version='>=1.0,<2.0']` is more explicit than the inline version
+                ("version", _) => ms.version = Some(compound_selector.clone()),
+                ("exclude", CompoundSelector::Single { selector: Selector::EqualTo, version }) => ms.exclude.push(version),
+                _ => (),
+            }
+        }
+
+        ms
+    }
+}
+
+impl From<MatchSpecRef<'_>> for MatchSpec {
+    fn from(ms: MatchSpecRef<'_>) -> Self {
+        let channels: Vec<String> = match ms.channel {
+            Some(channel) if channel.contains(',') => channel.split(',').map(String::from).collect(),
+            _ => Vec::new(),
+        };
+        MatchSpec {
+            channel: ms.channel.map(|channel| channel.split(',').next().unwrap_or_default().into()),
+            channels,
+            subdir: ms.subdir.map(String::from),
+            namespace: ms.namespace.map(String::from),
+            package: ms.package.into(),
+            version: ms.version.map(CompoundSelector::from),
+            build: ms.build.map(String::from),
+            build_number: ms.build_number.map(CompoundSelector::from),
+            key_value_pairs: ms
+                .key_value_pairs
+                .into_iter()
+                .map(|(key, cs)| (key.to_ascii_lowercase(), cs.into()))
+                .collect(),
+            exclude: ms.exclude.into_iter().map(String::from).collect(),
+            literal_package: false,
+            raw: None,
+        }
+    }
+}
+
+impl MatchSpecRef<'_> {
+    /// Matches package names, see `MatchSpec::is_package_match`
+    pub fn is_package_match(&self, package: &str) -> bool {
+        // Only `ParseOptions::literal_package` makes a literal name, which borrowing doesn't support
+        is_name_match(self.package, &self.exclude, false, package, false)
+    }
+
+    /// Matches only the version, see `MatchSpec::is_version_match`
+    pub fn is_version_match(&self, version: &str) -> bool {
+        is_version_match(self.version.as_ref(), version)
+    }
+
+    pub fn is_package_version_match(&self, package: &str, version: &str) -> bool {
+        self.is_package_match(package) && self.is_version_match(version)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod borrowed {
+        use crate::matchspec_ref::*;
+
+        #[test]
+        fn parse_borrowed() {
+            let s = "conda-forge/linux-64::numpy>=1.20,<2.0[build=py39*, build_number='>=2', exclude=numpy-base]";
+            let ms = MatchSpec::parse_borrowed(s).unwrap();
+
+            assert_eq!(ms.channel, Some("conda-forge"));
+            assert_eq!(ms.subdir, Some("linux-64"));
+            assert_eq!(ms.namespace, None);
+            assert_eq!(ms.package, "numpy");
+            assert_eq!(
+                ms.version,
+                Some(CompoundSelector::And {
+                    first_selector: Selector::GreaterThanOrEqualTo,
+                    first_version: "1.20",
+                    second_selector: Selector::LessThan,
+                    second_version: "2.0",
+                })
+            );
+            assert_eq!(ms.build, Some("py39*"));
+            assert_eq!(
+                ms.build_number,
+                Some(CompoundSelector::Single {
+                    selector: Selector::GreaterThanOrEqualTo,
+                    version: "2",
+                })
+            );
+            assert_eq!(ms.exclude, vec!["numpy-base"]);
+
+            // Everything is borrowed straight out of the input
+            let range = s.as_bytes().as_ptr_range();
+            assert!(range.contains(&ms.package.as_ptr()));
+            assert!(range.contains(&ms.build.unwrap().as_ptr()));
+
+            // The owned MatchSpec is the same as one parsed normally
+            assert_eq!(MatchSpec::from(ms), s.parse::<MatchSpec>().unwrap());
+        }
+
+        #[test]
+        fn parse_borrowed_implicit() {
+            let ms = MatchSpec::parse_borrowed("zstd 1.4.5 h9ceee32_0").unwrap();
+            assert_eq!(ms.package, "zstd");
+            assert_eq!(
+                ms.version,
+                Some(CompoundSelector::Single {
+                    selector: Selector::EqualTo,
+                    version: "1.4.5",
+                })
+            );
+            assert_eq!(ms.build, Some("h9ceee32_0"));
+
+            assert!(MatchSpec::parse_borrowed("python=wrong").is_err());
+        }
+
+        #[test]
+        fn version_key() {
+            let ms = MatchSpec::parse_borrowed("numpy[version='>=1.0,<2.0']").unwrap();
+            assert_eq!(
+                ms.version,
+                Some(CompoundSelector::And {
+                    first_selector: Selector::GreaterThanOrEqualTo,
+                    first_version: "1.0",
+                    second_selector: Selector::LessThan,
+                    second_version: "2.0",
+                })
+            );
+            assert!(ms.is_version_match("1.5"));
+            assert!(!ms.is_version_match("2.0"));
+
+            let ms: MatchSpec = "numpy[version=\">=1.0\"]".parse().unwrap();
+            assert_eq!(ms, "numpy>=1.0".parse::<MatchSpec>().unwrap());
+            assert_eq!(ms.to_string(), "numpy>=1.0");
+
+            let ms: MatchSpec = "numpy[version='1.0.*']".parse().unwrap();
+            assert!(ms.is_version_match("1.0.5"));
+            assert!(!ms.is_version_match("1.1"));
+        }
+
+        #[test]
+        fn mixed_case_keys() {
+            let ms = MatchSpec::parse_borrowed("numpy[Subdir=linux-64, BUILD=py39*, Build_Number='>=2']").unwrap();
+            assert_eq!(ms.subdir, Some("linux-64"));
+            assert_eq!(ms.build, Some("py39*"));
+            assert!(ms.build_number.is_some());
+
+            // The owned MatchSpec stores the keys lowercased, so lookups by name work
+            let ms = MatchSpec::from(ms);
+            assert_eq!(ms, "numpy[subdir=linux-64,build=py39*,build_number='>=2']".parse::<MatchSpec>().unwrap());
+            assert!(ms.key_values().contains_key("build_number"));
+
+            let ms: MatchSpec = "numpy[Extension=conda]".parse().unwrap();
+            assert!(ms.is_extension_match(Some("numpy-1.21.0-py39h_0.conda")));
+            assert!(!ms.is_extension_match(Some("numpy-1.21.0-py39h_0.tar.bz2")));
+        }
+
+        #[test]
+        fn borrowed_matching() {
+            let ms = MatchSpec::parse_borrowed("tensorflow*>=2.0[exclude=tensorflow-gpu]").unwrap();
+            assert!(ms.is_package_version_match("tensorflow", "2.9.1"));
+            assert!(ms.is_package_version_match("tensorflow-cpu", "2.9.1"));
+            assert!(!ms.is_package_version_match("tensorflow-gpu", "2.9.1"));
+            assert!(!ms.is_package_version_match("tensorflow", "1.15.0"));
+            assert!(!ms.is_package_version_match("pytorch", "2.0.0"));
+        }
+
+        /// The borrowed and owned MatchSpecs share their matching code, so they always agree
+        #[test]
+        fn matches_like_owned() {
+            let specs = ["numpy 1.11.*", "numpy>=1.2", "numpy!=1.4.*", "numpy<1.2.1", "num*[exclude=numba]", "numpy"];
+            let candidates = [
+                ("numpy", "1.11.3"),
+                ("numpy", "1.12a1"),
+                ("numpy", "1.2.*"),
+                ("numpy", "1.4.7"),
+                ("numba", "1.0"),
+                ("NumPy", "1.11.0"),
+            ];
+            for spec in specs {
+                let owned: MatchSpec = spec.parse().unwrap();
+                let borrowed = MatchSpec::parse_borrowed(spec).unwrap();
+                for (name, version) in candidates {
+                    assert_eq!(
+                        borrowed.is_package_version_match(name, version),
+                        owned.is_package_version_match(name, version),
+                        "{} {} {}",
+                        spec,
+                        name,
+                        version
+                    );
+                }
+            }
+            assert!(MatchSpec::parse_borrowed("numpy>=1.2").unwrap().is_version_match("1.2.*"));
+        }
+    }
+}
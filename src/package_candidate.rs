@@ -1,7 +1,9 @@
+use crate::error::MatchSpecError;
 use crate::matchspec::*;
 use pyo3::prelude::*;
 use pyo3::types::{PyAny, PyDict};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
 #[pyclass]
@@ -11,21 +13,194 @@ pub struct PackageCandidate {
     pub version: Option<String>,
     pub build: Option<String>,
     pub build_number: Option<u32>,
+    pub channel: Option<String>,
     #[serde(default = "Vec::new")]
     pub depends: Vec<String>,
+    pub filename: Option<String>,
     pub license: Option<String>,
     pub md5: Option<String>,
+    pub noarch: Option<String>,
     pub sha256: Option<String>,
     pub size: Option<u64>,
     pub subdir: Option<String>,
     pub timestamp: Option<u64>,
 }
 
+/// The parts of a repodata.json we care about. Both maps are keyed by the package filename.
+#[derive(Deserialize)]
+struct Repodata {
+    #[serde(default)]
+    packages: BTreeMap<String, PackageCandidate>,
+    #[serde(default, rename = "packages.conda")]
+    packages_conda: BTreeMap<String, PackageCandidate>,
+}
+
 // These are safe to assume because Option, String, and u64 are all Send/Sync
 unsafe impl Send for PackageCandidate {}
 
 unsafe impl Sync for PackageCandidate {}
 
+/// Anything that can be matched against a MatchSpec. Implement this to match your own package
+/// types without converting them to PackageCandidates first. Only the name, version, build,
+/// build_number and subdir are required, the rest default to unknown which never fails a match.
+/// ```
+/// use rust_matchspec::MatchSpec;
+/// use rust_matchspec::package_candidate::Candidate;
+///
+/// struct Installed(&'static str, &'static str);
+///
+/// impl Candidate for Installed {
+///     fn name(&self) -> &str { self.0 }
+///     fn version(&self) -> Option<&str> { Some(self.1) }
+///     fn build(&self) -> Option<&str> { None }
+///     fn build_number(&self) -> Option<u32> { None }
+///     fn subdir(&self) -> Option<&str> { None }
+/// }
+///
+/// let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+/// assert!(ms.is_match(&Installed("numpy", "1.26.4")));
+/// ```
+pub trait Candidate {
+    fn name(&self) -> &str;
+    fn version(&self) -> Option<&str>;
+    fn build(&self) -> Option<&str>;
+    fn build_number(&self) -> Option<u32>;
+    fn subdir(&self) -> Option<&str>;
+
+    /// The channel the candidate came from
+    fn channel(&self) -> Option<&str> {
+        None
+    }
+
+    /// The package filename, used for the `[extension=...]` key
+    fn filename(&self) -> Option<&str> {
+        None
+    }
+
+    /// The build timestamp in epoch milliseconds or seconds
+    fn timestamp(&self) -> Option<u64> {
+        None
+    }
+
+    /// The platform part of the subdir, like `linux` for `linux-64`. None for `noarch` packages
+    /// and candidates without a subdir.
+    fn platform(&self) -> Option<&str> {
+        self.subdir()?.split_once('-').map(|(platform, _)| platform)
+    }
+
+    /// The CPU architecture of the subdir, using the names conda reports for it. `linux-64` is
+    /// `x86_64`, `osx-arm64` is `arm64`. None for `noarch` packages and candidates without a
+    /// subdir.
+    fn arch(&self) -> Option<&str> {
+        match self.subdir()?.split_once('-')?.1 {
+            "64" => Some("x86_64"),
+            "32" => Some("x86"),
+            arch => Some(arch),
+        }
+    }
+}
+
+impl Candidate for PackageCandidate {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    fn build(&self) -> Option<&str> {
+        self.build.as_deref()
+    }
+
+    fn build_number(&self) -> Option<u32> {
+        self.build_number
+    }
+
+    fn subdir(&self) -> Option<&str> {
+        self.subdir.as_deref()
+    }
+
+    fn channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+}
+
+/// The borrowed fields behind `MatchSpec::is_match_fields`, so callers with columnar data don't
+/// need their own Candidate type
+pub(crate) struct CandidateFields<'a> {
+    pub name: &'a str,
+    pub version: Option<&'a str>,
+    pub build: Option<&'a str>,
+    pub build_number: Option<u32>,
+    pub subdir: Option<&'a str>,
+}
+
+impl Candidate for CandidateFields<'_> {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn version(&self) -> Option<&str> {
+        self.version
+    }
+
+    fn build(&self) -> Option<&str> {
+        self.build
+    }
+
+    fn build_number(&self) -> Option<u32> {
+        self.build_number
+    }
+
+    fn subdir(&self) -> Option<&str> {
+        self.subdir
+    }
+}
+
+/// Lets iterators of references be matched without dereferencing each item
+impl<T: Candidate + ?Sized> Candidate for &T {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn version(&self) -> Option<&str> {
+        (**self).version()
+    }
+
+    fn build(&self) -> Option<&str> {
+        (**self).build()
+    }
+
+    fn build_number(&self) -> Option<u32> {
+        (**self).build_number()
+    }
+
+    fn subdir(&self) -> Option<&str> {
+        (**self).subdir()
+    }
+
+    fn channel(&self) -> Option<&str> {
+        (**self).channel()
+    }
+
+    fn filename(&self) -> Option<&str> {
+        (**self).filename()
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        (**self).timestamp()
+    }
+}
+
 impl From<&str> for PackageCandidate {
     fn from(s: &str) -> Self {
         let package_candidate: PackageCandidate = serde_json::from_str(s).unwrap();
@@ -33,6 +208,124 @@ impl From<&str> for PackageCandidate {
     }
 }
 
+impl PackageCandidate {
+    /// Reads all of the packages out of a repodata.json, including both the `packages` and
+    /// `packages.conda` maps.
+    /// ```
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let repodata = r#"{"packages": {"zlib-1.2.13-h5eee18b_0.tar.bz2": {"name": "zlib", "version": "1.2.13"}}}"#;
+    /// let candidates = PackageCandidate::from_repodata_json(repodata).unwrap();
+    /// assert_eq!(candidates[0].name, "zlib");
+    /// ```
+    pub fn from_repodata_json(s: &str) -> Result<Vec<PackageCandidate>, serde_json::Error> {
+        let repodata: Repodata = serde_json::from_str(s)?;
+        Ok(repodata
+            .packages
+            .into_iter()
+            .chain(repodata.packages_conda)
+            .map(|(filename, pc)| PackageCandidate {
+                filename: Some(filename),
+                ..pc
+            })
+            .collect())
+    }
+
+    /// The platform part of the subdir, like `linux` for `linux-64`. None for `noarch` packages
+    /// and candidates without a subdir.
+    pub fn platform(&self) -> Option<&str> {
+        Candidate::platform(self)
+    }
+
+    /// The CPU architecture of the subdir, using the names conda reports for it. `linux-64` is
+    /// `x86_64`, `osx-arm64` is `arm64`. None for `noarch` packages and candidates without a
+    /// subdir.
+    /// ```
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let pc = PackageCandidate::from(r#"{"name": "numpy", "subdir": "linux-aarch64"}"#);
+    /// assert_eq!(pc.platform(), Some("linux"));
+    /// assert_eq!(pc.arch(), Some("aarch64"));
+    /// ```
+    pub fn arch(&self) -> Option<&str> {
+        Candidate::arch(self)
+    }
+
+    /// Checks whether this candidate has a `depends` entry for the package in the MatchSpec that
+    /// could be satisfied by the same versions. Depends entries without a version, or with a bare
+    /// `*` version, accept any version. A `noarch: python` package is installable under any
+    /// python, so its python entry accepts every python version. Entries that fail to parse are
+    /// skipped.
+    /// ```
+    /// use rust_matchspec::MatchSpec;
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let pc = PackageCandidate::from(r#"{"name": "six", "depends": ["python *"]}"#);
+    /// let ms: MatchSpec = "python>=3".parse().unwrap();
+    /// assert!(pc.depends_match(&ms));
+    /// ```
+    pub fn depends_match(&self, ms: &MatchSpec) -> bool {
+        self.depends
+            .iter()
+            .filter_map(|depend| depend.parse::<MatchSpec>().ok())
+            .filter(|depend| ms.is_package_match(depend.package.clone()))
+            .any(|depend| match (&ms.version, &depend.version) {
+                _ if self.is_noarch_python() && depend.package == "python" => true,
+                (Some(version), Some(depend_version)) => version.version_range_overlaps(depend_version),
+                _ => true,
+            })
+    }
+
+    /// Parses every `depends` entry into a MatchSpec, in the same order. Parsing is the expensive
+    /// part of checking depends, so callers matching against them repeatedly can keep the result
+    /// around. Fails on the first entry that doesn't parse.
+    /// ```
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let pc = PackageCandidate::from(r#"{"name": "six", "depends": ["python >=3.6"]}"#);
+    /// let depends = pc.depends_specs().unwrap();
+    /// assert!(depends[0].is_package_version_match("python", "3.10.4"));
+    /// ```
+    pub fn depends_specs(&self) -> Result<Vec<MatchSpec>, MatchSpecError> {
+        self.depends.iter().map(|depend| depend.parse()).collect()
+    }
+
+    /// Creates a PackageCandidate from a package filename in the `name-version-build.ext` form.
+    /// Both `.tar.bz2` and `.conda` packages are supported.
+    /// ```
+    /// use rust_matchspec::package_candidate::PackageCandidate;
+    ///
+    /// let pc = PackageCandidate::from_filename("ca-certificates-2023.01.10-h06a4308_0.conda").unwrap();
+    /// assert_eq!(pc.name, "ca-certificates");
+    /// assert_eq!(pc.version, Some("2023.01.10".to_string()));
+    /// assert_eq!(pc.build, Some("h06a4308_0".to_string()));
+    /// ```
+    pub fn from_filename(filename: &str) -> Result<Self, MatchSpecError> {
+        let stem = filename
+            .strip_suffix(".tar.bz2")
+            .or_else(|| filename.strip_suffix(".conda"))
+            .ok_or_else(|| MatchSpecError {
+                message: format!("Unknown package extension: {}", filename),
+            })?;
+
+        // Names can contain dashes, but versions and builds can't, so split from the right
+        match stem.rsplitn(3, '-').collect::<Vec<&str>>()[..] {
+            [build, version, name] if !name.is_empty() && !version.is_empty() && !build.is_empty() => {
+                Ok(PackageCandidate {
+                    name: name.to_string(),
+                    version: Some(version.to_string()),
+                    build: Some(build.to_string()),
+                    filename: Some(filename.to_string()),
+                    ..PackageCandidate::default()
+                })
+            }
+            _ => Err(MatchSpecError {
+                message: format!("Filename isn't in the name-version-build form: {}", filename),
+            }),
+        }
+    }
+}
+
 #[pymethods]
 impl PackageCandidate {
     #[new]
@@ -48,14 +341,19 @@ impl PackageCandidate {
         size: Option<u64>,
         subdir: Option<String>,
         timestamp: Option<u64>,
+        noarch: Option<String>,
+        filename: Option<String>,
     ) -> Self {
         PackageCandidate {
             name,
             version,
             build,
             build_number,
+            channel: None,
+            filename,
             license,
             md5,
+            noarch,
             sha256,
             size,
             subdir,
@@ -68,6 +366,11 @@ impl PackageCandidate {
         ms.is_match(self)
     }
 
+    /// True if this is a `noarch: python` package, which is installable under any python version
+    pub fn is_noarch_python(&self) -> bool {
+        self.noarch.as_deref() == Some("python")
+    }
+
     pub fn __repr__(&self) -> String {
         match (&self.name, &self.version, &self.build, &self.build_number) {
             (name, Some(version), Some(build), Some(build_number)) => {
@@ -99,12 +402,15 @@ impl PackageCandidate {
             build_number: dict
                 .get_item("build_number")
                 .and_then(|i| PyAny::extract(i).ok()),
+            channel: get("channel", dict),
             depends: dict
                 .get_item("build_number")
                 .and_then(|i| PyAny::extract::<Vec<String>>(i).ok())
                 .unwrap_or_default(),
+            filename: get("filename", dict),
             license: get("version", dict),
             md5: get("version", dict),
+            noarch: get("noarch", dict),
             sha256: get("version", dict),
             size: dict
                 .get_item("build_number")
@@ -124,6 +430,17 @@ impl TryFrom<&PyDict> for PackageCandidate {
     }
 }
 
+/// A candidate with only a name and version, for tests. Other fields can be filled in with
+/// `..test_candidate(name, version)`.
+#[cfg(test)]
+pub(crate) fn test_candidate(name: &str, version: &str) -> PackageCandidate {
+    PackageCandidate {
+        name: name.to_string(),
+        version: Some(version.to_string()),
+        ..PackageCandidate::default()
+    }
+}
+
 #[cfg(test)]
 mod test {
     #[cfg(test)]
@@ -176,5 +493,364 @@ mod test {
             let ms: MatchSpec = "main/linux-64::python>3.10".parse().unwrap();
             assert!(ms.is_match(&candidate))
         }
+
+        #[test]
+        fn test_build_number_range() {
+            let candidate = |build_number: u32| PackageCandidate {
+                build_number: Some(build_number),
+                ..test_candidate("python", "3.10.4")
+            };
+
+            let ms: MatchSpec = "python>3.6[build_number>=2,build_number<5]".parse().unwrap();
+            assert_eq!(
+                ms.build_number,
+                Some(CompoundSelector::from((">=", "2", ",", "<", "5")))
+            );
+            assert!(!ms.is_match(&candidate(1)));
+            assert!(ms.is_match(&candidate(2)));
+            assert!(ms.is_match(&candidate(3)));
+            assert!(!ms.is_match(&candidate(5)));
+
+            // The quoted compound form is equivalent
+            let ms: MatchSpec = "python>3.6[build_number='>=2,<5']".parse().unwrap();
+            assert!(ms.is_match(&candidate(3)));
+            assert!(!ms.is_match(&candidate(5)));
+
+            // Constraints that can't be combined are an error rather than being dropped
+            for spec in [
+                "python>3.6[build_number>=2,build_number<5,build_number!=3]",
+                "python>3.6[build_number='>=2,<5',build_number!=3]",
+                "python>3.6[build_number!=3,build_number='>=2,<5']",
+            ] {
+                let err = spec.parse::<MatchSpec>().unwrap_err();
+                assert!(err.message.contains("build_number"), "{}", err.message);
+                assert!(!MatchSpec::is_valid(spec), "{}", spec);
+            }
+        }
+
+        #[test]
+        fn missing_build_number() {
+            let candidate = test_candidate("python", "3.10.4");
+
+            let ms: MatchSpec = "python>3.6[build_number>=2]".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+            let ms: MatchSpec = "python>3.6[build_number='0']".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+
+            // Without a constraint the build_number doesn't matter
+            let ms: MatchSpec = "python>3.6".parse().unwrap();
+            assert!(ms.is_match(&candidate));
+        }
+
+        #[test]
+        fn repodata_json() {
+            let repodata = r#"{
+                  "info": {"subdir": "linux-64"},
+                  "packages": {
+                    "zlib-1.2.13-h5eee18b_0.tar.bz2": {
+                      "build": "h5eee18b_0",
+                      "build_number": 0,
+                      "depends": ["libgcc-ng >=11.2.0"],
+                      "name": "zlib",
+                      "subdir": "linux-64",
+                      "version": "1.2.13"
+                    },
+                    "python-3.10.4-h12debd9_0.tar.bz2": {
+                      "build": "h12debd9_0",
+                      "build_number": 0,
+                      "depends": ["openssl >=1.1.1n,<1.1.2a", "zlib >=1.2.12,<1.3.0a0"],
+                      "name": "python",
+                      "subdir": "linux-64",
+                      "version": "3.10.4"
+                    }
+                  },
+                  "packages.conda": {
+                    "zlib-1.2.13-h5eee18b_1.conda": {
+                      "build": "h5eee18b_1",
+                      "build_number": 1,
+                      "name": "zlib",
+                      "subdir": "linux-64",
+                      "version": "1.2.13"
+                    }
+                  },
+                  "removed": []
+                }"#;
+
+            let candidates = PackageCandidate::from_repodata_json(repodata).unwrap();
+            assert_eq!(candidates.len(), 3);
+            assert_eq!(candidates[0].name, "python");
+            assert_eq!(
+                candidates[0].filename,
+                Some("python-3.10.4-h12debd9_0.tar.bz2".to_string())
+            );
+            assert_eq!(candidates[0].depends.len(), 2);
+            assert_eq!(candidates[1].build, Some("h5eee18b_0".to_string()));
+            assert_eq!(candidates[2].build_number, Some(1));
+
+            let ms: MatchSpec = "zlib>=1.2.13".parse().unwrap();
+            assert_eq!(candidates.iter().filter(|pc| ms.is_match(pc)).count(), 2);
+
+            assert!(PackageCandidate::from_repodata_json("{\"packages\": []}").is_err());
+        }
+
+        #[test]
+        fn filenames() {
+            let pc = PackageCandidate::from_filename("numpy-base-1.21.0-py39h_0.tar.bz2").unwrap();
+            assert_eq!(pc.name, "numpy-base");
+            assert_eq!(pc.version, Some("1.21.0".to_string()));
+            assert_eq!(pc.build, Some("py39h_0".to_string()));
+            assert_eq!(pc.filename, Some("numpy-base-1.21.0-py39h_0.tar.bz2".to_string()));
+
+            let pc = PackageCandidate::from_filename("_libgcc_mutex-0.1-main.conda").unwrap();
+            assert_eq!(pc.name, "_libgcc_mutex");
+            assert_eq!(pc.version, Some("0.1".to_string()));
+            assert_eq!(pc.build, Some("main".to_string()));
+
+            assert!(PackageCandidate::from_filename("numpy-1.21.0-py39h_0.zip").is_err());
+            assert!(PackageCandidate::from_filename("numpy-1.21.0.conda").is_err());
+            assert!(PackageCandidate::from_filename("-1.21.0-py39h_0.conda").is_err());
+        }
+
+        #[test]
+        fn extension() {
+            let conda = PackageCandidate::from_filename("numpy-1.21.0-py39h_0.conda").unwrap();
+            let tarball = PackageCandidate::from_filename("numpy-1.21.0-py39h_0.tar.bz2").unwrap();
+            let no_filename = PackageCandidate::from(r#"{"name": "numpy", "version": "1.21.0"}"#);
+
+            let ms: MatchSpec = "numpy>=1.20[extension=conda]".parse().unwrap();
+            assert!(ms.is_match(&conda));
+            assert!(!ms.is_match(&tarball));
+            assert!(!ms.is_match(&no_filename));
+
+            let ms: MatchSpec = "numpy>=1.20[extension='.tar.bz2']".parse().unwrap();
+            assert!(!ms.is_match(&conda));
+            assert!(ms.is_match(&tarball));
+
+            let ms: MatchSpec = "numpy>=1.20[extension='!=conda']".parse().unwrap();
+            assert!(!ms.is_match(&conda));
+            assert!(ms.is_match(&tarball));
+
+            // Ordering a format doesn't mean anything, so it isn't taken as `=conda`
+            let ms: MatchSpec = "numpy>=1.20[extension='>=conda']".parse().unwrap();
+            assert!(!ms.is_match(&conda));
+            assert!(!ms.is_match(&tarball));
+
+            // Without an extension key the format doesn't matter
+            let ms: MatchSpec = "numpy>=1.20".parse().unwrap();
+            assert!(ms.is_match(&conda));
+            assert!(ms.is_match(&tarball));
+            assert!(ms.is_match(&no_filename));
+        }
+
+        #[test]
+        fn noarch_python() {
+            let payload = r#"{
+                  "build": "pyhd3eb1b0_0",
+                  "build_number": 0,
+                  "depends": ["python >=3.6"],
+                  "name": "six",
+                  "noarch": "python",
+                  "subdir": "noarch",
+                  "version": "1.16.0"
+                }"#;
+            let candidate = PackageCandidate::from(payload);
+            assert!(candidate.is_noarch_python());
+
+            // A subdir specific spec doesn't exclude noarch python packages
+            let ms: MatchSpec = "main/linux-64::six>=1.15".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+            assert!(ms.satisfied_by_noarch(&candidate));
+
+            // But everything else still has to match
+            let ms: MatchSpec = "main/linux-64::six<1.15".parse().unwrap();
+            assert!(!ms.satisfied_by_noarch(&candidate));
+
+            // Regular packages are never satisfied this way
+            let candidate = PackageCandidate::from(r#"{"name": "six", "version": "1.16.0", "subdir": "linux-64"}"#);
+            let ms: MatchSpec = "six>=1.15".parse().unwrap();
+            assert!(!candidate.is_noarch_python());
+            assert!(!ms.satisfied_by_noarch(&candidate));
+        }
+
+        #[test]
+        fn noarch_python_depends() {
+            let noarch = PackageCandidate::from(
+                r#"{"name": "six", "noarch": "python", "depends": ["python >=3.6", "setuptools >=40"]}"#,
+            );
+            let arch = PackageCandidate::from(r#"{"name": "six", "depends": ["python >=3.6", "setuptools >=40"]}"#);
+
+            // Any python satisfies the python depends of a noarch python package
+            for spec in ["python 2.7", "python >=3.6", "python <3"] {
+                let ms: MatchSpec = spec.parse().unwrap();
+                assert!(noarch.depends_match(&ms), "{}", spec);
+            }
+            assert!(!arch.depends_match(&"python 2.7".parse().unwrap()));
+            assert!(arch.depends_match(&"python >=3.6".parse().unwrap()));
+
+            // Other depends are still checked normally
+            assert!(!noarch.depends_match(&"setuptools <40".parse().unwrap()));
+            assert!(!noarch.depends_match(&"numpy".parse().unwrap()));
+
+            let candidates = vec![noarch, arch];
+            let ms: MatchSpec = "python 2.7".parse().unwrap();
+            let dependents = ms.dependents(&candidates);
+            assert_eq!(dependents.len(), 1);
+            assert!(dependents[0].is_noarch_python());
+        }
+
+        #[test]
+        fn timestamp() {
+            // Built 2018-08-15
+            let payload = r#"{"name": "python", "version": "3.7.0", "timestamp": 1534356589107}"#;
+            let candidate = PackageCandidate::from(payload);
+
+            let ms: MatchSpec = "python[timestamp>=2018-01-01]".parse().unwrap();
+            assert!(ms.is_match(&candidate));
+            let ms: MatchSpec = "python[timestamp>=2021-01-01]".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+            let ms: MatchSpec = "python[timestamp>='2018-08-15T18:00:00Z']".parse().unwrap();
+            assert!(ms.is_match(&candidate));
+            let ms: MatchSpec = "python[timestamp>='2018-08-15T19:00:00+00:00']".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+            let ms: MatchSpec = "python[timestamp='>=2018-01-01,<2019-01-01']".parse().unwrap();
+            assert!(ms.is_match(&candidate));
+            let ms: MatchSpec = "python[timestamp='<2018-01-01|>=2019-01-01']".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+            let ms: MatchSpec = "python[timestamp<1534356589108]".parse().unwrap();
+            assert!(ms.is_match(&candidate));
+            // A fraction that isn't digits can't be compared, instead of panicking
+            let ms: MatchSpec = "python[timestamp>='2018-01-01T12:00:00.12éZ']".parse().unwrap();
+            assert!(!ms.is_match(&candidate));
+
+            // Older packages have timestamps in seconds
+            let candidate = PackageCandidate::from(r#"{"name": "python", "version": "2.7.0", "timestamp": 1534356589}"#);
+            let ms: MatchSpec = "python[timestamp>=2018-01-01]".parse().unwrap();
+            assert!(ms.is_match(&candidate));
+
+            // A timestamp constraint can't be met without a timestamp
+            let candidate = PackageCandidate::from(r#"{"name": "python", "version": "3.7.0"}"#);
+            assert!(!ms.is_match(&candidate));
+        }
+
+        #[test]
+        fn platform_and_arch() {
+            let candidate = |subdir: &str| PackageCandidate {
+                subdir: Some(subdir.to_string()),
+                ..test_candidate("numpy", "1.26.4")
+            };
+
+            let ms: MatchSpec = "numpy[arch=x86_64]".parse().unwrap();
+            assert!(ms.is_match(&candidate("linux-64")));
+            assert!(ms.is_match(&candidate("win-64")));
+            assert!(!ms.is_match(&candidate("linux-aarch64")));
+            assert!(!ms.is_match(&candidate("noarch")));
+
+            let ms: MatchSpec = "numpy[platform=linux]".parse().unwrap();
+            assert!(ms.is_match(&candidate("linux-64")));
+            assert!(ms.is_match(&candidate("linux-ppc64le")));
+            assert!(!ms.is_match(&candidate("osx-arm64")));
+            assert!(!ms.is_match(&PackageCandidate { subdir: None, ..candidate("linux-64") }));
+
+            let ms: MatchSpec = "numpy[platform=osx, arch='!=x86_64']".parse().unwrap();
+            assert!(ms.is_match(&candidate("osx-arm64")));
+            assert!(!ms.is_match(&candidate("osx-64")));
+            assert!(!ms.is_match(&candidate("linux-aarch64")));
+
+            assert_eq!(candidate("win-32").arch(), Some("x86"));
+            assert_eq!(candidate("noarch").platform(), None);
+        }
+
+        #[test]
+        fn depends_glob() {
+            let payload = r#"{
+                  "name": "six",
+                  "version": "1.16.0",
+                  "depends": ["python *", "zlib >=1.2.12,<1.3.0a0"]
+                }"#;
+            let candidate = PackageCandidate::from(payload);
+
+            // `python *` accepts any python version
+            let ms: MatchSpec = "python>=3".parse().unwrap();
+            assert!(candidate.depends_match(&ms));
+            let ms: MatchSpec = "python 2.7.18".parse().unwrap();
+            assert!(candidate.depends_match(&ms));
+            let ms: MatchSpec = "python *".parse().unwrap();
+            assert!(ms.is_version_match("3.10.4"));
+
+            // Version ranges still have to overlap
+            let ms: MatchSpec = "zlib>=1.2".parse().unwrap();
+            assert!(candidate.depends_match(&ms));
+            let ms: MatchSpec = "zlib>=1.3".parse().unwrap();
+            assert!(!candidate.depends_match(&ms));
+
+            // Packages that aren't depended on never match
+            let ms: MatchSpec = "numpy".parse().unwrap();
+            assert!(!candidate.depends_match(&ms));
+        }
+
+        #[test]
+        fn depends_specs() {
+            let payload = r#"{
+                  "name": "openssl",
+                  "version": "1.1.1q",
+                  "depends": ["ca-certificates", "libgcc-ng >=11.2.0", "zlib >=1.2.12,<1.3.0a0"]
+                }"#;
+            let candidate = PackageCandidate::from(payload);
+
+            let depends = candidate.depends_specs().unwrap();
+            assert_eq!(depends.len(), 3);
+            assert_eq!(depends[0], "ca-certificates".parse::<MatchSpec>().unwrap());
+            assert_eq!(depends[1].package, "libgcc-ng");
+            assert!(depends[2].is_package_version_match("zlib", "1.2.13"));
+            assert!(!depends[2].is_package_version_match("zlib", "1.3.0"));
+
+            let candidate = PackageCandidate {
+                depends: vec!["python >=3.6".to_string(), "python=wrong".to_string()],
+                ..candidate
+            };
+            assert!(candidate.depends_specs().is_err());
+        }
+
+        #[test]
+        fn custom_candidate() {
+            struct Locked {
+                name: String,
+                version: String,
+                platform: &'static str,
+            }
+
+            impl Candidate for Locked {
+                fn name(&self) -> &str {
+                    &self.name
+                }
+                fn version(&self) -> Option<&str> {
+                    Some(&self.version)
+                }
+                fn build(&self) -> Option<&str> {
+                    None
+                }
+                fn build_number(&self) -> Option<u32> {
+                    None
+                }
+                fn subdir(&self) -> Option<&str> {
+                    Some(self.platform)
+                }
+            }
+
+            let locked = Locked {
+                name: "numpy".to_string(),
+                version: "1.26.4".to_string(),
+                platform: "osx-arm64",
+            };
+            assert!("numpy>=1.20".parse::<MatchSpec>().unwrap().is_match(&locked));
+            assert!("numpy[subdir=osx-arm64]".parse::<MatchSpec>().unwrap().is_match(&locked));
+            assert!("numpy[arch=arm64]".parse::<MatchSpec>().unwrap().is_match(&locked));
+            assert!(!"numpy<1.20".parse::<MatchSpec>().unwrap().is_match(&locked));
+            assert!(!"numpy[subdir=linux-64]".parse::<MatchSpec>().unwrap().is_match(&locked));
+            assert!(!"numpy[build=py39*]".parse::<MatchSpec>().unwrap().is_match(&locked));
+
+            // Unknown channels can't fail a match
+            assert!("conda-forge::numpy".parse::<MatchSpec>().unwrap().is_match(&locked));
+        }
     }
 }
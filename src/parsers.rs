@@ -1,19 +1,31 @@
 use crate::input_table::*;
 use crate::matchspec::*;
+use crate::matchspec_ref::MatchSpecRef;
 use nom::error::{Error as NomError, ErrorKind};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while, take_while1},
     character::complete::{
-        alphanumeric0, multispace0, multispace1, one_of, satisfy,
+        alphanumeric0, char, digit1, one_of, satisfy,
     },
-    combinator::{complete, eof, opt, peek},
+    combinator::{complete, consumed, eof, map, opt, peek},
     multi::separated_list0,
-    sequence::{delimited, terminated, tuple},
+    sequence::{delimited, preceded, terminated, tuple},
     IResult,
 };
 use version_compare::Version;
 
+/// Like nom's `multispace0`, but also skips Unicode whitespace like non-breaking spaces, which
+/// show up in specs copied out of web pages and documents
+fn whitespace0(s: &str) -> IResult<&str, &str> {
+    take_while(char::is_whitespace)(s)
+}
+
+/// Like nom's `multispace1`, but also accepts Unicode whitespace
+fn whitespace1(s: &str) -> IResult<&str, &str> {
+    take_while1(char::is_whitespace)(s)
+}
+
 /// Parses a version selector. Possible values:
 /// | Selector | Function                                                                   |
 /// |----------|----------------------------------------------------------------------------|
@@ -21,15 +33,14 @@ use version_compare::Version;
 /// | <        | Less Than                                                                  |
 /// | >=       | Greater Than or Equal To                                                   |
 /// | <=       | Less Than or Equal To                                                      |
+/// | ===      | Exactly Equal, including any `+build` metadata                             |
 /// | ==       | Equal                                                                      |
 /// | =        | Equal                                                                      |
 /// | !=       | Not Equal                                                                  |
 /// | ~=       | [Compatible Release](https://peps.python.org/pep-0440/#compatible-release) |
-///
-/// *Note:* Compatible Release is not yet supported and will be mapped to `Selector::EqualTo`
 pub(crate) fn selector_parser(s: &str) -> IResult<&str, &str> {
     delimited(
-        multispace0,
+        whitespace0,
         alt((
             tag("==="),
             tag("!="),
@@ -41,7 +52,7 @@ pub(crate) fn selector_parser(s: &str) -> IResult<&str, &str> {
             tag(">"),
             tag("<"),
         )),
-        multispace0,
+        whitespace0,
     )(s)
 }
 
@@ -50,10 +61,13 @@ pub(crate) fn name_parser(s: &str) -> IResult<&str, &str> {
     take_while1(is_any_valid_str_with_glob)(s)
 }
 
-/// Parses the package version
+/// Parses the package version, which can start with an epoch like `1!2.0`
 pub(crate) fn version_parser(s: &str) -> IResult<&str, &str> {
-    let (remainder, version) = take_while1(is_any_valid_str_with_glob)(s)?;
-    match Version::from(version) {
+    let (remainder, (version, (_, without_epoch))) = consumed(tuple((
+        opt(terminated(digit1, char('!'))),
+        take_while1(is_version_str_with_glob),
+    )))(s)?;
+    match Version::from(without_epoch) {
         Some(_) => Ok((remainder, version)),
         None => Err(nom::Err::Failure(NomError {
             code: ErrorKind::Fail,
@@ -63,66 +77,223 @@ pub(crate) fn version_parser(s: &str) -> IResult<&str, &str> {
 }
 
 fn version_and_selector_parser(s: &str) -> IResult<&str, (&str, &str)> {
-    tuple((selector_parser, version_parser))(s)
+    let (remainder, (selector, version)) = tuple((selector_parser, version_parser))(s)?;
+    // A compatible release needs at least two segments to work out its upper bound
+    if selector == "~=" && compatible_upper_bound(version).is_none() {
+        return Err(nom::Err::Failure(NomError {
+            code: ErrorKind::Verify,
+            input: version,
+        }));
+    }
+    Ok((remainder, (selector, version)))
 }
 
-pub(crate) fn compound_selector_parser(s: &str) -> IResult<&str, CompoundSelector<String>> {
+/// Parses one or two selector and version pairs joined by `,`, `|` or `||`, borrowing the versions from
+/// the input.
+pub(crate) fn compound_selector_ref_parser(s: &str) -> IResult<&str, CompoundSelector<&str>> {
+    // Some tools write the alt joiner as `||`, which means the same thing as `|`
+    let joiner = alt((map(tag("||"), |_| '|'), satisfy(is_comma_or_alt)));
     let result = tuple((
         version_and_selector_parser,
-        delimited(multispace0, satisfy(is_comma_or_alt), multispace0),
+        delimited(whitespace0, joiner, whitespace0),
         version_and_selector_parser,
     ))(s);
 
     // If we can parse via the more exhaustive parser, return that.
     match result {
-        Ok((remainder, parsed)) => Ok((remainder, parsed.into())),
+        Ok((remainder, ((first_selector, first_version), joiner, (second_selector, second_version)))) => {
+            let (first_selector, second_selector) = (first_selector.into(), second_selector.into());
+            let cs = match joiner {
+                '|' => CompoundSelector::Or { first_selector, first_version, second_selector, second_version },
+                _ => CompoundSelector::And { first_selector, first_version, second_selector, second_version },
+            };
+            Ok((remainder, cs))
+        }
+        Err(nom::Err::Failure(err)) => Err(nom::Err::Failure(err)),
         Err(_) => match version_and_selector_parser(s) {
-            Ok((remainder, parsed)) => Ok((remainder, parsed.into())),
+            Ok((remainder, (selector, version))) => Ok((
+                remainder,
+                CompoundSelector::Single {
+                    selector: selector.into(),
+                    version,
+                },
+            )),
             Err(err) => Err(err),
         },
     }
 }
 
-/// Parses the channel
+pub(crate) fn compound_selector_parser(s: &str) -> IResult<&str, CompoundSelector<String>> {
+    compound_selector_ref_parser(s).map(|(remainder, cs)| (remainder, cs.into()))
+}
+
+/// Parses the channel, or a `,` separated list of channels in priority order. Every channel in a
+/// list has to be named, so `conda-forge,::numpy` is an error.
 pub(crate) fn channel_parser(s: &str) -> IResult<&str, &str> {
-    terminated(take_while(is_alphanumeric_with_dashes), peek(one_of(":/")))(s)
+    let (remainder, channel) = terminated(
+        take_while(|c| is_alphanumeric_with_dashes(c) || is_comma(c)),
+        peek(one_of(":/")),
+    )(s)?;
+    if channel.contains(',') && channel.split(',').any(str::is_empty) {
+        return Err(nom::Err::Failure(NomError {
+            code: ErrorKind::Verify,
+            input: channel,
+        }));
+    }
+    Ok((remainder, channel))
 }
 
 // Helper parser for key value parser
-pub(crate) fn value_parser(s: &str) -> IResult<&str, CompoundSelector<String>> {
+pub(crate) fn value_parser(s: &str) -> IResult<&str, CompoundSelector<&str>> {
     let result = delimited(
-        multispace0,
-        take_while1(is_alphanumeric_with_dashes),
-        multispace0,
+        whitespace0,
+        tuple((opt(selector_parser), take_while1(is_any_valid_str_with_glob))),
+        whitespace0,
     )(s);
 
     match result {
-        Ok((remainder, parsed)) => Ok((remainder, CompoundSelector::from(("=", parsed)))),
+        Ok((remainder, (selector, parsed))) => Ok((
+            remainder,
+            CompoundSelector::Single {
+                selector: selector.unwrap_or("=").into(),
+                version: parsed,
+            },
+        )),
         Err(err) => Err(err),
     }
 }
 
-/// Parses a single key_value_pair:
+// Everything between a pair of matching quotes
+fn quoted_str_parser(s: &str) -> IResult<&str, &str> {
+    let (s, quote) = satisfy(is_quote)(s)?;
+    terminated(take_while1(move |c| c != quote), char(quote))(s)
+}
+
+// A selector directly followed by a value that isn't a version: `timestamp>=2021-01-01`
+fn selector_value_parser(s: &str) -> IResult<&str, CompoundSelector<&str>> {
+    let (remainder, (selector, value)) = delimited(
+        whitespace0,
+        tuple((selector_parser, alt((quoted_str_parser, take_while1(is_any_valid_str_with_glob))))),
+        whitespace0,
+    )(s)?;
+    Ok((
+        remainder,
+        CompoundSelector::Single {
+            selector: selector.into(),
+            version: value,
+        },
+    ))
+}
+
+// Quoted values can contain any character other than a quote, like the `:` in a timestamp. If the
+// quoted text parses as a selector and value that's used, otherwise the whole text is the value.
+fn quoted_value_parser(s: &str) -> IResult<&str, CompoundSelector<&str>> {
+    let (s, quote) = satisfy(is_quote)(s)?;
+    let parsed = terminated(alt((key_value_compound_selector_parser, value_parser)), char(quote))(s);
+    if parsed.is_ok() {
+        return parsed;
+    }
+
+    let (remainder, (selector, value)) = terminated(
+        tuple((opt(selector_parser), take_while1(move |c| c != quote))),
+        char(quote),
+    )(s)?;
+    Ok((
+        remainder,
+        CompoundSelector::Single {
+            selector: selector.unwrap_or("=").into(),
+            version: value,
+        },
+    ))
+}
+
+// Key values aren't always versions, so a failed version parse in the compound_selector_parser
+// needs to fall back to the value_parser instead of failing the whole parse.
+fn key_value_compound_selector_parser(s: &str) -> IResult<&str, CompoundSelector<&str>> {
+    match compound_selector_ref_parser(s) {
+        Err(nom::Err::Failure(err)) => Err(nom::Err::Error(err)),
+        result => result,
+    }
+}
+
+/// Parses a single key_value_pair, borrowing the key and value from the input:
 /// `key='value'`
 /// `key='>=value'`
 /// `key='<value'`
+/// `key>=value`
 //  'build='py_sfsafas_1''
-pub(crate) fn key_value_pair_parser(s: &str) -> IResult<&str, (&str, CompoundSelector<String>)> {
+pub(crate) fn key_value_pair_ref_parser(s: &str) -> IResult<&str, (&str, CompoundSelector<&str>)> {
     let name_parser = delimited(
-        multispace0,
+        whitespace0,
         take_while1(is_alphanumeric_with_dashes),
-        delimited(multispace0, tag("="), multispace0),
-    );
-    let value_parser = delimited(
-        opt(satisfy(is_quote)),
-        alt((compound_selector_parser, value_parser)),
-        opt(complete(satisfy(is_quote))),
+        delimited(whitespace0, tag("="), whitespace0),
     );
+    let value_parser = alt((
+        quoted_value_parser,
+        delimited(
+            opt(satisfy(is_quote)),
+            alt((key_value_compound_selector_parser, value_parser)),
+            opt(complete(satisfy(is_quote))),
+        ),
+    ));
+
+    // The selector can also directly follow the key: `build_number>=2`
+    let name_with_selector_parser = tuple((
+        preceded(whitespace0, take_while1(is_alphanumeric_with_dashes)),
+        alt((key_value_compound_selector_parser, selector_value_parser)),
+    ));
 
     delimited(
-        multispace0,
-        tuple((name_parser, value_parser)),
-        multispace0,
+        whitespace0,
+        alt((tuple((name_parser, value_parser)), name_with_selector_parser)),
+        whitespace0,
+    )(s)
+}
+
+/// Parses the English exclusion form `numpy not 1.19.*` into a `NotEqualTo` version. This is only
+/// used when `ParseOptions::not_keyword` is set.
+pub(crate) fn not_keyword_matchspec_ref_parser(s: &str) -> IResult<&str, MatchSpecRef<'_>> {
+    let (remainder, (package, version)) = tuple((
+        take_while1(is_alphanumeric_with_dashes_or_period),
+        delimited(
+            delimited(whitespace1, tag("not"), whitespace1),
+            version_parser,
+            terminated(whitespace0, eof),
+        ),
+    ))(s)?;
+
+    Ok((
+        remainder,
+        MatchSpecRef {
+            version: Some(CompoundSelector::Single {
+                selector: Selector::NotEqualTo,
+                version,
+            }),
+            ..MatchSpecRef::from((package, None, None))
+        },
+    ))
+}
+
+/// Eats `/subdir`
+fn subdir_parser(s: &str) -> IResult<&str, &str> {
+    delimited(
+        satisfy(is_forward_slash),
+        take_while(is_alphanumeric_with_dashes),
+        peek(satisfy(is_colon)),
+    )(s)
+}
+
+/// Eats `:namespace:`
+fn namespace_parser(s: &str) -> IResult<&str, &str> {
+    delimited(satisfy(is_colon), alphanumeric0, satisfy(is_colon))(s)
+}
+
+/// Parses only the package name, skipping over any `channel/subdir:namespace:` prefix. Nothing
+/// after the name is looked at.
+pub(crate) fn package_name_parser(s: &str) -> IResult<&str, &str> {
+    preceded(
+        tuple((opt(channel_parser), opt(subdir_parser), opt(namespace_parser))),
+        name_parser,
     )(s)
 }
 
@@ -134,52 +305,98 @@ pub(crate) fn key_value_pair_parser(s: &str) -> IResult<&str, (&str, CompoundSel
 /// python 2.7.*
 /// _libgcc_mutex 0.1 main
 /// backports_abc 0.5 py27h7b3c97b_0
+/// python 3.10 py3*
 /// ```
-pub(crate) fn implicit_matchspec_parser(s: &str) -> IResult<&str, MatchSpec> {
+pub(crate) fn implicit_matchspec_ref_parser(s: &str) -> IResult<&str, MatchSpecRef<'_>> {
     let (remainder, t) = tuple((
         take_while1(is_alphanumeric_with_dashes_or_period),
-        opt(delimited(multispace1, version_parser, multispace0)),
-        opt(take_while1(is_alphanumeric_with_dashes_or_period)),
+        // The build can only follow a version, otherwise a glob in the name would be a build
+        opt(tuple((
+            delimited(whitespace1, version_parser, whitespace0),
+            opt(take_while1(is_any_valid_str_with_glob)),
+        ))),
         eof,
     ))(s)?;
 
-    let output = (t.0, t.1, t.2);
+    let output = match t.1 {
+        Some((version, build)) => (t.0, Some(version), build),
+        None => (t.0, None, None),
+    };
     Ok((remainder, output.into()))
 }
 
-/// Parses the whole matchspec using Nom, returning a `MatchSpecTuple`
-/// Assumes this format:
-/// `(channel(/subdir):(namespace):)name(version(build))[key1=value1,key2=value2]`
-/// Instead of using this directly please use the `"".parse()` style provided by FromStr
-pub(crate) fn full_matchspec_parser(s: &str) -> IResult<&str, MatchSpec, NomError<&str>> {
-    // Eats `/subdir`
-    let subdir_parser = delimited(
-        satisfy(is_forward_slash),
-        take_while(is_alphanumeric_with_dashes),
-        peek(satisfy(is_colon)),
-    );
+/// Owned version of the `implicit_matchspec_ref_parser`
+pub(crate) fn implicit_matchspec_parser(s: &str) -> IResult<&str, MatchSpec> {
+    implicit_matchspec_ref_parser(s).map(|(remainder, ms)| (remainder, ms.into()))
+}
 
-    // Eats `:namespace:`
-    let namespace_parser = delimited(satisfy(is_colon), alphanumeric0, satisfy(is_colon));
+/// True if the `build_number` keys can be combined into one CompoundSelector, which holds at most
+/// two single constraints
+fn is_build_number_representable(keys: &[(&str, CompoundSelector<&str>)]) -> bool {
+    let build_numbers: Vec<&CompoundSelector<&str>> = keys
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("build_number"))
+        .map(|(_, cs)| cs)
+        .collect();
+    matches!(
+        build_numbers[..],
+        [] | [_] | [CompoundSelector::Single { .. }, CompoundSelector::Single { .. }]
+    )
+}
 
+/// Parses the whole matchspec using Nom, borrowing all of the fields from the input
+/// Assumes this format:
+/// `(channel(/subdir):(namespace):)name(version( build|=build))[key1=value1,key2=value2]`
+/// Instead of using this directly please use the `"".parse()` style provided by FromStr
+pub(crate) fn full_matchspec_ref_parser(s: &str) -> IResult<&str, MatchSpecRef<'_>, NomError<&str>> {
     // Eats `[ .. ]`
     let keys_vec_parser = delimited(
         satisfy(is_left_bracket),
-        separated_list0(satisfy(is_comma), key_value_pair_parser),
+        separated_list0(satisfy(is_comma), key_value_pair_ref_parser),
         satisfy(is_right_bracket),
     );
 
+    // Eats ` py39*`, the build that repodata depends put after a version range, or the `=h5eee18b_0`
+    // in the `name=version=build` form that `conda list --export` writes
+    let build_parser = preceded(
+        alt((whitespace1, tag("="))),
+        take_while1(is_any_valid_str_with_glob),
+    );
+
     // Put all the parsers together
-    let (remainder, t) = complete(tuple((
+    let (remainder, (channel, subdir, ns, package, version, keys)) = complete(tuple((
         opt(channel_parser),
         opt(subdir_parser),
         opt(namespace_parser),
         name_parser,
-        opt(compound_selector_parser),
+        opt(tuple((compound_selector_ref_parser, opt(build_parser)))),
         opt(keys_vec_parser),
     )))(s)?;
 
-    Ok((remainder, t.into()))
+    // Rather than silently dropping build_number constraints that don't fit
+    if !is_build_number_representable(keys.as_deref().unwrap_or_default()) {
+        return Err(nom::Err::Failure(NomError {
+            code: ErrorKind::Verify,
+            input: "at most two single build_number constraints can be combined",
+        }));
+    }
+
+    let (cs, build) = match version {
+        Some((cs, build)) => (Some(cs), build),
+        None => (None, None),
+    };
+    let mut ms = MatchSpecRef::from((channel, subdir, ns, package, cs, keys));
+    // A `[build=...]` key is more explicit, so it wins
+    if ms.build.is_none() {
+        ms.build = build;
+    }
+
+    Ok((remainder, ms))
+}
+
+/// Owned version of the `full_matchspec_ref_parser`
+pub(crate) fn full_matchspec_parser(s: &str) -> IResult<&str, MatchSpec, NomError<&str>> {
+    full_matchspec_ref_parser(s).map(|(remainder, ms)| (remainder, ms.into()))
 }
 
 #[cfg(test)]
@@ -199,6 +416,15 @@ mod test {
                 channel_parser("main/linux-64::tensorflow >=2.9.1"),
                 Ok(("/linux-64::tensorflow >=2.9.1", "main"))
             );
+
+            assert_eq!(
+                channel_parser("conda-forge,defaults::numpy"),
+                Ok(("::numpy", "conda-forge,defaults"))
+            );
+            for s in ["conda-forge,::numpy", "a,,b::x", ",defaults::numpy", "a,/linux-64::x"] {
+                assert!(matches!(channel_parser(s), Err(nom::Err::Failure(_))), "{}", s);
+                assert!(s.parse::<MatchSpec>().is_err(), "{}", s);
+            }
         }
 
         #[test]
@@ -246,6 +472,7 @@ mod test {
             assert_eq!(version_parser("5.0.0.1"), Ok(("", "5.0.0.1")));
             assert_eq!(version_parser("2022.1"), Ok(("", "2022.1")));
             assert_eq!(version_parser("1.21_5"), Ok(("", "1.21_5")));
+            assert_eq!(version_parser("1.2.3+build5"), Ok(("", "1.2.3+build5")));
             assert_eq!(
                 version_parser("2.9.1[subdir=linux]"),
                 Ok(("[subdir=linux]", "2.9.1"))
@@ -261,6 +488,11 @@ mod test {
 
         #[test]
         fn test_key_value_parser() {
+            let key_value_pair_parser = |s| {
+                key_value_pair_ref_parser(s)
+                    .map(|(rest, (key, cs))| (rest, (key, CompoundSelector::<String>::from(cs))))
+            };
+
             // Ensure we handle quoting
             assert_eq!(
                 key_value_pair_parser("subdir = 'linux-64'"),
@@ -283,6 +515,39 @@ mod test {
                 key_value_pair_parser("subdir=linux-64"),
                 Ok(("", ("subdir", CompoundSelector::from(("=", "linux-64"))))),
             );
+
+            // Values that aren't versions can still have selectors
+            assert_eq!(
+                key_value_pair_parser("extension='!=conda'"),
+                Ok(("", ("extension", CompoundSelector::from(("!=", "conda"))))),
+            );
+
+            // Selectors can directly follow the key
+            assert_eq!(
+                key_value_pair_parser("build_number>=2"),
+                Ok(("", ("build_number", CompoundSelector::from((">=", "2"))))),
+            );
+
+            assert_eq!(
+                key_value_pair_parser(" build_number < 5 "),
+                Ok(("", ("build_number", CompoundSelector::from(("<", "5"))))),
+            );
+
+            // Values that follow a selector don't have to be versions
+            assert_eq!(
+                key_value_pair_parser("timestamp>=1534356589107"),
+                Ok(("", ("timestamp", CompoundSelector::from((">=", "1534356589107"))))),
+            );
+
+            // Quoted values can contain anything but the quote
+            assert_eq!(
+                key_value_pair_parser("timestamp>='2021-01-01T12:00:00Z'"),
+                Ok(("", ("timestamp", CompoundSelector::from((">=", "2021-01-01T12:00:00Z"))))),
+            );
+            assert_eq!(
+                key_value_pair_parser("license='GPL v2'"),
+                Ok(("", ("license", CompoundSelector::from(("=", "GPL v2"))))),
+            );
         }
 
         #[test]
@@ -420,8 +685,12 @@ mod test {
                     version: "2.9.1".to_string(),
                 }),
                 key_value_pairs: Vec::new(),
+                exclude: Vec::new(),
+                literal_package: false,
+                raw: None,
                 build: Some("mkl_py39hb9fcb14_0".to_string()),
                 channel: None,
+                channels: Vec::new(),
                 subdir: None,
                 build_number: None,
                 namespace: None,
@@ -501,6 +770,7 @@ mod test {
 
             let expected = MatchSpec {
                 channel: Some("main".to_string()),
+                channels: Vec::new(),
                 subdir: Some("linux-64".to_string()),
                 namespace: None,
                 package: "pytorch".to_string(),
@@ -511,6 +781,9 @@ mod test {
                     version: "1.10.2".to_string(),
                 }),
                 key_value_pairs: Vec::new(),
+                exclude: Vec::new(),
+                literal_package: false,
+                raw: None,
             };
 
             assert_eq!(ms, expected);
@@ -520,6 +793,7 @@ mod test {
         fn everything_specified() {
             let expected = MatchSpec {
                 channel: Some("conda-forge".to_string()),
+                channels: Vec::new(),
                 subdir: Some("linux-64".to_string()),
                 namespace: Some("UNUSED".to_string()),
                 package: "tensorflow".to_string(),
@@ -532,6 +806,9 @@ mod test {
                 }),
                 build_number: None,
                 key_value_pairs: Vec::new(),
+                exclude: Vec::new(),
+                literal_package: false,
+                raw: None,
             };
 
             let ms: MatchSpec =
@@ -548,18 +825,68 @@ mod test {
             assert_eq!(
                 ms,
                 Err(MatchSpecError {
-                    message: "Version parse failed".to_string()
+                    message: "Unable to parse MatchSpec 'python=wrong': Version parse failed".to_string()
                 })
             )
         }
     }
 
+    #[cfg(test)]
+    mod trailing_build {
+        use crate::matchspec::*;
+        use crate::package_candidate::{test_candidate, PackageCandidate};
+
+        #[test]
+        fn version_range_and_build() {
+            let ms: MatchSpec = "numpy >=1.20 py39*".parse().unwrap();
+            assert_eq!(ms.package, "numpy");
+            assert_eq!(
+                ms.version,
+                Some(CompoundSelector::Single {
+                    selector: Selector::GreaterThanOrEqualTo,
+                    version: "1.20".to_string(),
+                })
+            );
+            assert_eq!(ms.build, Some("py39*".to_string()));
+
+            let candidate = |version: &str, build: &str| PackageCandidate {
+                build: Some(build.to_string()),
+                ..test_candidate("numpy", version)
+            };
+            assert!(ms.is_match(&candidate("1.21.5", "py39h7a5d4dd_0")));
+            assert!(!ms.is_match(&candidate("1.21.5", "py310h7a5d4dd_0")));
+            assert!(!ms.is_match(&candidate("1.19.5", "py39h7a5d4dd_0")));
+
+            let ms: MatchSpec = "numpy >=1.20,<2.0a0 py39*".parse().unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from((">=", "1.20", ",", "<", "2.0a0"))));
+            assert_eq!(ms.build, Some("py39*".to_string()));
+
+            // The build key takes precedence over the trailing build
+            let ms: MatchSpec = "numpy >=1.20 py39*[build=py310*]".parse().unwrap();
+            assert_eq!(ms.build, Some("py310*".to_string()));
+            let ms: MatchSpec = "numpy >=1.20[subdir=linux-64]".parse().unwrap();
+            assert_eq!(ms.build, None);
+            assert_eq!(ms.subdir, Some("linux-64".to_string()));
+        }
+
+        #[test]
+        fn equals_separated_build() {
+            let ms: MatchSpec = "zlib=1.2.13=h5eee18b_0".parse().unwrap();
+            assert_eq!(ms.version, Some(CompoundSelector::from(("=", "1.2.13"))));
+            assert_eq!(ms.build, Some("h5eee18b_0".to_string()));
+
+            let ms: MatchSpec = "numpy==1.11.2=*nomkl*".parse().unwrap();
+            assert_eq!(ms.build, Some("*nomkl*".to_string()));
+            assert!(MatchSpec::is_valid("zlib=1.2.13=h5eee18b_0"));
+        }
+    }
+
     // This is a suite of tests using real data from things like the repodata.json
     #[cfg(test)]
     mod real_life {
-        use crate::matchspec::MatchSpec;
+        use crate::repodata::unparseable_lines;
         use std::fs::File;
-        use std::io::{BufRead, BufReader};
+        use std::io::BufReader;
 
         /// This is a test that loads data from repodata.json
         /// Here's how that file was generated:
@@ -575,16 +902,14 @@ mod test {
             );
             let repodata_depends_buffer =
                 BufReader::new(File::open(depends_file).expect("opening repodata depends file"));
-            let depends: Vec<String> = repodata_depends_buffer
-                .lines()
-                .map(|l| l.unwrap())
-                .collect();
 
-            for line in depends {
-                let _parsed: MatchSpec = line
-                    .parse()
-                    .unwrap_or_else(|_| panic!("Failed to parse: {}", line));
-            }
+            // Collect every failure instead of stopping at the first, so one run shows them all
+            let failures = unparseable_lines(repodata_depends_buffer).expect("reading repodata depends file");
+            let report: Vec<String> = failures
+                .iter()
+                .map(|(line_number, line, e)| format!("{}: {} ({})", line_number, line, e))
+                .collect();
+            assert_eq!(failures.len(), 0, "Failed to parse:\n{}", report.join("\n"));
         }
     }
 }
@@ -11,6 +11,8 @@ fn rust_matchspec(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(filter_package_list, m)?)?;
     m.add_function(wrap_pyfunction!(parallel_filter_package_list, m)?)?;
     m.add_function(wrap_pyfunction!(parallel_filter_package_list_with_matchspec_list, m)?)?;
+    m.add_function(wrap_pyfunction!(parallel_filter_package_list_all, m)?)?;
+    m.add_function(wrap_pyfunction!(match_mask, m)?)?;
     m.add_class::<MatchSpec>()?;
     m.add_class::<PackageCandidate>()?;
     Ok(())
@@ -33,7 +35,7 @@ fn try_pylist_into_vec_of_package_candidates(
 #[pyfunction]
 #[pyo3(signature = (matchspec, package, version))]
 fn match_against_matchspec(matchspec: String, package: String, version: String) -> bool {
-    let ms: MatchSpec = matchspec.parse().unwrap();
+    let ms = MatchSpec::parse_borrowed(&matchspec).unwrap();
     ms.is_package_version_match(&package, &version)
 }
 
@@ -127,3 +129,111 @@ fn parallel_filter_package_list_with_matchspec_list(
         .flat_map(|ms| filter_package_vec(ms, &package_candidate_list))
         .collect())
 }
+
+/// Helper function to filter a list of PackageCandidate down to the ones matching every MatchSpec
+fn filter_package_vec_all(
+    matchspecs: &[MatchSpec],
+    package_list: &[PackageCandidate],
+) -> Vec<PackageCandidate> {
+    package_list
+        .par_iter()
+        .with_min_len(1000)
+        .filter(|pc| matchspecs.iter().all(|ms| pc.is_match(ms)))
+        .cloned()
+        .collect()
+}
+
+/// Takes a list of package dictionaries and filters it based on a list of matchspecs. Unlike
+/// `parallel_filter_package_list_with_matchspec_list` a package candidate is only returned if it
+/// matches all of the given matchspecs, so there are never any duplicates.
+#[pyfunction]
+#[pyo3(signature = (matchspecs, package_list))]
+fn parallel_filter_package_list_all(
+    matchspecs: Vec<String>,
+    package_list: &PyList,
+) -> Result<Vec<PackageCandidate>, PyErr> {
+    let mut matchspec_list: Vec<MatchSpec> = Vec::new();
+    for maybe_matchspec in matchspecs {
+        let ms: MatchSpec = maybe_matchspec.parse()?;
+        matchspec_list.push(ms);
+    }
+
+    let package_candidate_list: Vec<PackageCandidate> =
+        try_pylist_into_vec_of_package_candidates(package_list)?;
+
+    Ok(filter_package_vec_all(&matchspec_list, &package_candidate_list))
+}
+
+/// Helper function to check each PackageCandidate against a MatchSpec, keeping the input order
+fn match_mask_vec(matchspec: &MatchSpec, package_list: &[PackageCandidate]) -> Vec<bool> {
+    package_list.iter().map(|pc| pc.is_match(matchspec)).collect()
+}
+
+/// Takes a list of package dictionaries and returns a list of booleans, one per package, telling
+/// whether that package matches the matchspec. Unlike the filter functions the result always has
+/// the same length and order as the input, so it can be used as a mask.
+#[pyfunction]
+#[pyo3(signature = (matchspec, package_list))]
+fn match_mask(matchspec: String, package_list: &PyList) -> PyResult<Vec<bool>> {
+    let ms: MatchSpec = matchspec.parse()?;
+    let package_candidate_list = try_pylist_into_vec_of_package_candidates(package_list)?;
+
+    Ok(match_mask_vec(&ms, &package_candidate_list))
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod filtering {
+        use crate::python::*;
+
+        #[test]
+        fn filter_all() {
+            let candidates: Vec<PackageCandidate> = [
+                r#"{"name": "pytorch", "version": "1.11.1", "build_number": 0}"#,
+                r#"{"name": "pytorch", "version": "2.0.0", "build_number": 0}"#,
+                r#"{"name": "pytorch", "version": "2.0.0", "build_number": 3}"#,
+                r#"{"name": "tensorflow", "version": "2.10.0", "build_number": 3}"#,
+            ]
+            .into_iter()
+            .map(PackageCandidate::from)
+            .collect();
+            let matchspecs: Vec<MatchSpec> = ["pytorch>1.12", "*[build_number=3]"]
+                .iter()
+                .map(|ms| ms.parse().unwrap())
+                .collect();
+
+            // Matching any spec includes the candidates that only match one of them
+            let any: Vec<PackageCandidate> = matchspecs
+                .iter()
+                .flat_map(|ms| filter_package_vec(ms, &candidates))
+                .collect();
+            assert_eq!(any.len(), 4);
+
+            let all = filter_package_vec_all(&matchspecs, &candidates);
+            assert_eq!(all.len(), 1);
+            assert_eq!(all[0].version, Some("2.0.0".to_string()));
+            assert_eq!(all[0].build_number, Some(3));
+
+            assert_eq!(filter_package_vec_all(&[], &candidates).len(), 4);
+        }
+
+        #[test]
+        fn mask() {
+            let candidates: Vec<PackageCandidate> = [
+                r#"{"name": "pytorch", "version": "1.11.1", "build_number": 0}"#,
+                r#"{"name": "tensorflow", "version": "2.10.0", "build_number": 3}"#,
+                r#"{"name": "pytorch", "version": "2.0.0", "build_number": 0}"#,
+                r#"{"name": "pytorch", "version": "1.13.0", "build_number": 3}"#,
+            ]
+            .into_iter()
+            .map(PackageCandidate::from)
+            .collect();
+            let ms: MatchSpec = "pytorch>1.12".parse().unwrap();
+
+            // One entry per candidate, in the same order, including the ones that don't match
+            assert_eq!(match_mask_vec(&ms, &candidates), vec![false, false, true, true]);
+            assert!(match_mask_vec(&ms, &[]).is_empty());
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::error::MatchSpecError;
+use crate::matchspec::MatchSpec;
+use crate::package_candidate::PackageCandidate;
+use serde::de::Error as _;
+use std::io::BufRead;
+
+/// Where the stream is in the repodata document
+enum State {
+    Start,
+    /// Between the top level keys, `first` is false once a key has been read
+    TopLevel { first: bool },
+    /// Inside the `packages` or `packages.conda` map
+    Packages { first: bool },
+    Done,
+}
+
+/// Pulls one package at a time out of a repodata.json, see `stream_repodata`
+struct RepodataStream<R> {
+    reader: R,
+    state: State,
+}
+
+impl<R: BufRead> RepodataStream<R> {
+    fn peek(&mut self) -> Result<Option<u8>, serde_json::Error> {
+        let buf = self.reader.fill_buf().map_err(serde_json::Error::io)?;
+        Ok(buf.first().copied())
+    }
+
+    fn next_byte(&mut self) -> Result<u8, serde_json::Error> {
+        let byte = self
+            .peek()?
+            .ok_or_else(|| serde_json::Error::custom("unexpected end of repodata"))?;
+        self.reader.consume(1);
+        Ok(byte)
+    }
+
+    /// Skips whitespace and returns the next byte without consuming it
+    fn peek_token(&mut self) -> Result<u8, serde_json::Error> {
+        while let Some(byte) = self.peek()? {
+            if !byte.is_ascii_whitespace() {
+                return Ok(byte);
+            }
+            self.reader.consume(1);
+        }
+        Err(serde_json::Error::custom("unexpected end of repodata"))
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), serde_json::Error> {
+        match self.peek_token()? {
+            byte if byte == expected => {
+                self.reader.consume(1);
+                Ok(())
+            }
+            byte => Err(serde_json::Error::custom(format!(
+                "expected '{}' in repodata, found '{}'",
+                expected as char, byte as char
+            ))),
+        }
+    }
+
+    /// Copies the rest of a string, after its opening quote, into `raw`
+    fn read_string_raw(&mut self, raw: &mut Vec<u8>) -> Result<(), serde_json::Error> {
+        loop {
+            let byte = self.next_byte()?;
+            raw.push(byte);
+            match byte {
+                b'\\' => raw.push(self.next_byte()?),
+                b'"' => return Ok(()),
+                _ => (),
+            }
+        }
+    }
+
+    /// Copies the next JSON value into `raw` without parsing it
+    fn read_value_raw(&mut self, raw: &mut Vec<u8>) -> Result<(), serde_json::Error> {
+        self.peek_token()?;
+        let mut depth = 0usize;
+        loop {
+            match self.peek()? {
+                Some(b'"') => {
+                    raw.push(self.next_byte()?);
+                    self.read_string_raw(raw)?;
+                }
+                Some(byte @ (b'{' | b'[')) => {
+                    depth += 1;
+                    raw.push(byte);
+                    self.reader.consume(1);
+                }
+                Some(byte @ (b'}' | b']')) if depth > 0 => {
+                    depth -= 1;
+                    raw.push(byte);
+                    self.reader.consume(1);
+                }
+                // A scalar ends at the next separator
+                Some(b',' | b'}' | b']') | None if depth == 0 => return Ok(()),
+                Some(byte) if depth == 0 && byte.is_ascii_whitespace() => return Ok(()),
+                Some(byte) => {
+                    raw.push(byte);
+                    self.reader.consume(1);
+                }
+                None => return Err(serde_json::Error::custom("unexpected end of repodata")),
+            }
+            // A string, object or array is complete once it's closed at the top level
+            if depth == 0 && raw.last().map(|b| matches!(b, b'}' | b']' | b'"')).unwrap_or(false) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads an object key and the `:` after it
+    fn read_key(&mut self) -> Result<String, serde_json::Error> {
+        let mut raw = Vec::new();
+        self.expect(b'"')?;
+        raw.push(b'"');
+        self.read_string_raw(&mut raw)?;
+        self.expect(b':')?;
+        serde_json::from_slice(&raw)
+    }
+
+    fn advance(&mut self) -> Result<Option<(String, PackageCandidate)>, serde_json::Error> {
+        loop {
+            match self.state {
+                State::Start => {
+                    self.expect(b'{')?;
+                    self.state = State::TopLevel { first: true };
+                }
+                State::TopLevel { first } => {
+                    if self.peek_token()? == b'}' {
+                        self.state = State::Done;
+                        continue;
+                    }
+                    if !first {
+                        self.expect(b',')?;
+                    }
+                    let key = self.read_key()?;
+                    if (key == "packages" || key == "packages.conda") && self.peek_token()? == b'{' {
+                        self.reader.consume(1);
+                        self.state = State::Packages { first: true };
+                    } else {
+                        self.read_value_raw(&mut Vec::new())?;
+                        self.state = State::TopLevel { first: false };
+                    }
+                }
+                State::Packages { first } => {
+                    if self.peek_token()? == b'}' {
+                        self.reader.consume(1);
+                        self.state = State::TopLevel { first: false };
+                        continue;
+                    }
+                    if !first {
+                        self.expect(b',')?;
+                    }
+                    let filename = self.read_key()?;
+                    let mut raw = Vec::new();
+                    self.read_value_raw(&mut raw)?;
+                    let pc: PackageCandidate = serde_json::from_slice(&raw)?;
+                    self.state = State::Packages { first: false };
+                    return Ok(Some((
+                        filename.clone(),
+                        PackageCandidate {
+                            filename: Some(filename),
+                            ..pc
+                        },
+                    )));
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RepodataStream<R> {
+    type Item = Result<(String, PackageCandidate), serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(package) => package.map(Ok),
+            Err(err) => {
+                // Nothing after an error can be trusted, so stop there
+                self.state = State::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Reads the packages out of a repodata.json one at a time, from both the `packages` and
+/// `packages.conda` maps in the order they appear. Only one package is held in memory at a time,
+/// so this works on repodata that is too large to load with
+/// `PackageCandidate::from_repodata_json`. The iterator stops after the first error.
+/// ```
+/// use rust_matchspec::repodata::stream_repodata;
+///
+/// let repodata = r#"{"packages": {"zlib-1.2.13-h5eee18b_0.tar.bz2": {"name": "zlib", "version": "1.2.13"}}}"#;
+/// let (filename, pc) = stream_repodata(repodata.as_bytes()).next().unwrap().unwrap();
+/// assert_eq!(filename, "zlib-1.2.13-h5eee18b_0.tar.bz2");
+/// assert_eq!(pc.name, "zlib");
+/// ```
+pub fn stream_repodata<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<(String, PackageCandidate), serde_json::Error>> {
+    RepodataStream {
+        reader,
+        state: State::Start,
+    }
+}
+
+/// Parses every line as a MatchSpec, like a list of repodata depends, and returns the lines that
+/// fail with their 1-based line number and error. Every line is tried, so one pass surfaces all
+/// the specs the parser can't handle rather than just the first.
+/// ```
+/// use rust_matchspec::repodata::unparseable_lines;
+///
+/// let depends = "libgcc-ng >=11.2.0\n==1.0\nzlib 1.2.13 h5eee18b_0\n";
+/// let failures = unparseable_lines(depends.as_bytes()).unwrap();
+/// assert_eq!(failures.len(), 1);
+/// assert_eq!((failures[0].0, failures[0].1.as_str()), (2, "==1.0"));
+/// ```
+pub fn unparseable_lines<R: BufRead>(reader: R) -> std::io::Result<Vec<(usize, String, MatchSpecError)>> {
+    let mut failures = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if let Err(e) = line.parse::<MatchSpec>() {
+            failures.push((index + 1, line, e));
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod streaming {
+        use crate::package_candidate::PackageCandidate;
+        use crate::repodata::*;
+        use std::fs::File;
+        use std::io::BufReader;
+
+        const REPODATA: &str = r#"{
+          "info": {"subdir": "linux-64", "nested": [{"a": "}"}, 1.5, null]},
+          "packages": {
+            "zlib-1.2.13-h5eee18b_0.tar.bz2": {
+              "build": "h5eee18b_0",
+              "build_number": 0,
+              "depends": ["libgcc-ng >=11.2.0"],
+              "name": "zlib",
+              "version": "1.2.13"
+            },
+            "openssl-1.1.1q-h7f8727e_0.tar.bz2": {
+              "name": "openssl",
+              "version": "1.1.1q",
+              "license": "OpenSSL \"with\" quotes"
+            }
+          },
+          "packages.conda": {
+            "zlib-1.2.13-h5eee18b_1.conda": {"name": "zlib", "version": "1.2.13", "build_number": 1}
+          },
+          "removed": [],
+          "repodata_version": 1
+        }"#;
+
+        #[test]
+        fn small_repodata() {
+            let packages: Vec<(String, PackageCandidate)> = stream_repodata(REPODATA.as_bytes())
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            let filenames: Vec<&str> = packages.iter().map(|(filename, _)| filename.as_str()).collect();
+            assert_eq!(
+                filenames,
+                vec![
+                    "zlib-1.2.13-h5eee18b_0.tar.bz2",
+                    "openssl-1.1.1q-h7f8727e_0.tar.bz2",
+                    "zlib-1.2.13-h5eee18b_1.conda"
+                ]
+            );
+            assert_eq!(packages[0].1.depends, vec!["libgcc-ng >=11.2.0"]);
+            assert_eq!(packages[0].1.filename.as_deref(), Some("zlib-1.2.13-h5eee18b_0.tar.bz2"));
+            assert_eq!(packages[1].1.license.as_deref(), Some("OpenSSL \"with\" quotes"));
+            assert_eq!(packages[2].1.build_number, Some(1));
+        }
+
+        #[test]
+        fn invalid_repodata() {
+            let mut stream = stream_repodata(r#"{"packages": {"a.conda": {"version": "1.0"}"#.as_bytes());
+            // The package is missing its name
+            assert!(stream.next().unwrap().is_err());
+            assert!(stream.next().is_none());
+
+            let mut stream = stream_repodata(r#"{"packages": {"a.conda": {"name": "a"}"#.as_bytes());
+            assert!(stream.next().unwrap().is_ok());
+            assert!(stream.next().unwrap().is_err());
+        }
+
+        #[test]
+        fn unparseable_depends() {
+            let path = format!("{}/test_data/bad-depends.txt", env!("CARGO_MANIFEST_DIR"));
+            let file = BufReader::new(File::open(path).expect("opening bad depends"));
+            let failures = unparseable_lines(file).unwrap();
+
+            let lines: Vec<(usize, &str)> = failures.iter().map(|(n, line, _)| (*n, line.as_str())).collect();
+            assert_eq!(lines, vec![(2, "==1.0"), (5, ">=2.0,<3")]);
+            assert!(failures[0].2.message.contains("==1.0"));
+        }
+
+        /// Streaming gives the same packages as loading the whole file
+        #[test]
+        fn real_repodata() {
+            let path = format!("{}/test_data/repodata-linux-64.json", env!("CARGO_MANIFEST_DIR"));
+            let file = BufReader::new(File::open(path.clone()).expect("opening repodata"));
+            let mut streamed: Vec<String> = stream_repodata(file).map(|package| package.unwrap().0).collect();
+            streamed.sort();
+
+            let loaded = PackageCandidate::from_repodata_json(&std::fs::read_to_string(path).unwrap()).unwrap();
+            let mut loaded: Vec<String> = loaded.into_iter().map(|pc| pc.filename.unwrap()).collect();
+            loaded.sort();
+
+            assert_eq!(streamed, loaded);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// The most compiled globs kept per thread. A long running process can see any number of
+/// different globs, so the cache starts over once it's full rather than growing forever.
+const MAX_COMPILED: usize = 256;
+
+thread_local! {
+    /// Compiled globs, since the same few globs get matched against every candidate
+    static COMPILED: RefCell<HashMap<String, Option<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// True if the string uses any of the shell glob syntax beyond `*`
+pub(crate) fn has_shell_glob(s: &str) -> bool {
+    s.contains(['?', '['])
+}
+
+/// Translates a shell glob into an anchored regex. `*` is any run of characters, `?` is any one
+/// character, and `[a-z]` or `[!a-z]` is one character that is or isn't in the class. A `[`
+/// without a closing `]` is matched literally.
+fn to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let rest: String = chars.clone().collect();
+                // A `]` right after the `[` or `[!` is part of the class, like in shells
+                let start = if rest.starts_with('!') { 1 } else { 0 };
+                let Some(end) = rest[start..]
+                    .char_indices()
+                    .skip(1)
+                    .find(|(_, c)| *c == ']')
+                    .map(|(i, _)| i + start)
+                else {
+                    regex.push_str(&regex::escape("["));
+                    continue;
+                };
+                let class = &rest[start..end];
+                regex.push('[');
+                if start == 1 {
+                    regex.push('^');
+                }
+                // Only `-` keeps its meaning inside the class
+                for c in class.chars() {
+                    match c {
+                        '\\' | '[' | ']' | '^' | '&' | '~' => {
+                            regex.push('\\');
+                            regex.push(c);
+                        }
+                        c => regex.push(c),
+                    }
+                }
+                regex.push(']');
+                for _ in 0..rest[..=end].chars().count() {
+                    chars.next();
+                }
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Matches a string against a shell glob. Globs that don't make a valid pattern, like a class
+/// with a backwards range, never match.
+pub(crate) fn is_match_shell_glob(glob: &str, s: &str) -> bool {
+    COMPILED.with(|compiled| {
+        let mut compiled = compiled.borrow_mut();
+        if compiled.len() >= MAX_COMPILED && !compiled.contains_key(glob) {
+            compiled.clear();
+        }
+        compiled
+            .entry(glob.to_string())
+            .or_insert_with(|| Regex::new(&to_regex(glob)).ok())
+            .as_ref()
+            .map(|regex| regex.is_match(s))
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod shell_glob {
+        use crate::shell_glob::*;
+
+        #[test]
+        fn translation() {
+            assert_eq!(to_regex("py3*"), "^py3.*$");
+            assert_eq!(to_regex("py3?"), "^py3.$");
+            assert_eq!(to_regex("py3[0-9]"), "^py3[0-9]$");
+            assert_eq!(to_regex("py3[!0-9]"), "^py3[^0-9]$");
+            assert_eq!(to_regex("a.b"), "^a\\.b$");
+            assert_eq!(to_regex("a[b"), "^a\\[b$");
+            assert_eq!(to_regex("a[]b]"), "^a[\\]b]$");
+        }
+
+        #[test]
+        fn matching() {
+            assert!(is_match_shell_glob("py3[0-9]_0", "py39_0"));
+            assert!(!is_match_shell_glob("py3[0-9]_0", "py3a_0"));
+            assert!(is_match_shell_glob("py3[!0-9]*", "py3a_0"));
+            assert!(is_match_shell_glob("h?ab*", "h1abc"));
+            assert!(!is_match_shell_glob("h?ab*", "hab"));
+            assert!(is_match_shell_glob("a[b", "a[b"));
+            assert!(!is_match_shell_glob("[z-a]", "b"));
+        }
+
+        #[test]
+        fn bounded_cache() {
+            for i in 0..MAX_COMPILED * 2 {
+                assert!(is_match_shell_glob(&format!("{}?", i), &format!("{}x", i)));
+            }
+            assert!(COMPILED.with(|compiled| compiled.borrow().len()) <= MAX_COMPILED);
+        }
+    }
+}
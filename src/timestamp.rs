@@ -0,0 +1,118 @@
+/// Repodata timestamps are epoch milliseconds, but older packages used seconds. Anything before
+/// the year 9999 in seconds is assumed to be seconds, which is how conda handles it too.
+pub(crate) fn normalize_timestamp_millis(timestamp: u64) -> u64 {
+    if timestamp > 253_402_300_799 {
+        timestamp
+    } else {
+        timestamp * 1000
+    }
+}
+
+/// Days since 1970-01-01 for a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parses a timestamp as given in a `[timestamp>=...]` key into epoch milliseconds. This can be a
+/// plain epoch timestamp in seconds or milliseconds, an ISO date like `2021-01-01`, or an RFC3339
+/// date and time like `2021-01-01T12:00:00Z`. Times without an offset are UTC.
+pub(crate) fn parse_timestamp_millis(value: &str) -> Option<u64> {
+    let number = |s: &str| -> Option<i64> {
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    };
+
+    if let Some(epoch) = number(value) {
+        return Some(normalize_timestamp_millis(u64::try_from(epoch).ok()?));
+    }
+
+    let (date, time) = value
+        .split_once(['T', 't', ' '])
+        .unwrap_or((value, ""));
+
+    let mut date_parts = date.splitn(3, '-');
+    let year = number(date_parts.next()?)?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Split off the offset from UTC, if there is one
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, 0)
+    } else if let Some(i) = time.rfind(['+', '-']) {
+        let sign = if time[i..].starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = time[i + 1..].split_once(':')?;
+        (&time[..i], sign * (number(hours)? * 3600 + number(minutes)? * 60))
+    } else {
+        (time, 0)
+    };
+
+    let (time, millis) = match time.split_once('.') {
+        Some((_, fraction)) if !fraction.bytes().all(|b| b.is_ascii_digit()) => return None,
+        Some((time, fraction)) => (time, number(&format!("{:0<3}", &fraction[..fraction.len().min(3)]))?),
+        None => (time, 0),
+    };
+
+    let seconds = if time.is_empty() {
+        0
+    } else {
+        let mut time_parts = time.splitn(3, ':');
+        let hours = number(time_parts.next()?)?;
+        let minutes = number(time_parts.next()?)?;
+        let seconds = time_parts.next().map(number).unwrap_or(Some(0))?;
+        if hours > 23 || minutes > 59 || seconds > 60 {
+            return None;
+        }
+        hours * 3600 + minutes * 60 + seconds
+    };
+
+    let total = (days_from_civil(year, month, day) * 86_400 + seconds - offset) * 1000 + millis;
+    u64::try_from(total).ok()
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod timestamps {
+        use crate::timestamp::*;
+
+        #[test]
+        fn epoch_timestamps() {
+            assert_eq!(parse_timestamp_millis("1534356589107"), Some(1534356589107));
+            assert_eq!(parse_timestamp_millis("1534356589"), Some(1534356589000));
+            assert_eq!(normalize_timestamp_millis(0), 0);
+        }
+
+        #[test]
+        fn dates() {
+            assert_eq!(parse_timestamp_millis("1970-01-01"), Some(0));
+            assert_eq!(parse_timestamp_millis("2021-01-01"), Some(1609459200000));
+            assert_eq!(parse_timestamp_millis("2024-02-29"), Some(1709164800000));
+            assert_eq!(parse_timestamp_millis("2021-13-01"), None);
+            assert_eq!(parse_timestamp_millis("1969-12-31"), None);
+            assert_eq!(parse_timestamp_millis("yesterday"), None);
+        }
+
+        #[test]
+        fn rfc3339() {
+            assert_eq!(parse_timestamp_millis("2021-01-01T12:00:00Z"), Some(1609502400000));
+            assert_eq!(parse_timestamp_millis("2021-01-01T12:00:00"), Some(1609502400000));
+            assert_eq!(parse_timestamp_millis("2021-01-01 12:00:00.25Z"), Some(1609502400250));
+            assert_eq!(parse_timestamp_millis("2021-01-01T14:00:00+02:00"), Some(1609502400000));
+            assert_eq!(parse_timestamp_millis("2021-01-01T10:00:00-02:00"), Some(1609502400000));
+            assert_eq!(parse_timestamp_millis("2021-01-01T25:00:00Z"), None);
+            assert_eq!(parse_timestamp_millis("2021-01-01T12:00:00.12éZ"), None);
+            assert_eq!(parse_timestamp_millis("2021-01-01T12:00:00.1é3Z"), None);
+        }
+    }
+}
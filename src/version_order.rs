@@ -0,0 +1,174 @@
+use std::cmp::Ordering;
+
+/// One run of digits or letters inside a version component. The variants are declared in the order
+/// conda sorts them: `dev` comes before any other tag, tags come before numbers, and `post` comes
+/// after everything.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Part<'a> {
+    Dev,
+    Tag(Tag<'a>),
+    Number(Number<'a>),
+    Post,
+}
+
+/// A letter tag like `a`, `rc` or `g`, compared case insensitively
+#[derive(Debug, PartialEq, Eq)]
+struct Tag<'a>(&'a str);
+
+impl PartialOrd for Tag<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Tag<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let lower = |s: &str| s.bytes().map(|b| b.to_ascii_lowercase()).collect::<Vec<u8>>();
+        lower(self.0).cmp(&lower(other.0))
+    }
+}
+
+/// A run of digits without leading zeros. Comparing the digits instead of parsing them means
+/// numbers of any size can be ordered, like the dates some packages use as versions.
+#[derive(Debug, PartialEq, Eq)]
+struct Number<'a>(&'a str);
+
+impl<'a> Number<'a> {
+    fn new(digits: &'a str) -> Self {
+        Number(digits.trim_start_matches('0'))
+    }
+}
+
+impl PartialOrd for Number<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.len().cmp(&other.0.len()).then_with(|| self.0.cmp(other.0))
+    }
+}
+
+const ZERO: Part<'static> = Part::Number(Number(""));
+
+/// Splits off the epoch, like the `1` in `1!0.4`. Versions without one are in epoch 0.
+fn split_epoch(version: &str) -> (Number<'_>, &str) {
+    match version.trim().split_once('!') {
+        Some((epoch, version)) => (Number::new(epoch), version),
+        None => (Number(""), version),
+    }
+}
+
+/// Splits a version into its components
+fn components(version: &str) -> Vec<Vec<Part<'_>>> {
+    version.trim().split(['.', '_', '-']).map(parts).collect()
+}
+
+/// Splits one component, like `1rc2`, into its runs of digits and letters. Components that start
+/// with a letter get an implicit leading zero, so `1.rc2` orders the same as `1.0rc2`.
+fn parts(component: &str) -> Vec<Part<'_>> {
+    let mut parts = Vec::new();
+    let mut rest = component;
+    while let Some(first) = rest.chars().next() {
+        let is_digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (run, remainder) = rest.split_at(end);
+        parts.push(if is_digit {
+            Part::Number(Number::new(run))
+        } else if run.eq_ignore_ascii_case("dev") {
+            Part::Dev
+        } else if run.eq_ignore_ascii_case("post") {
+            Part::Post
+        } else {
+            Part::Tag(Tag(run))
+        });
+        rest = remainder;
+    }
+
+    if !matches!(parts.first(), None | Some(Part::Number(_))) {
+        parts.insert(0, ZERO);
+    }
+    parts
+}
+
+/// Orders two versions the way conda does. Versions are split into components on `.`, `_` and
+/// `-`, and missing components count as zero, so `1.0 == 1.0.0`. Letters make a pre-release:
+/// `1.1a1 < 1.1` and `1.1.1a < 1.1.1`. The `dev` tag orders before every other tag and `post`
+/// orders after everything, so `1.0dev1 < 1.0a1 < 1.0 < 1.0post1`. An epoch like the `1` in
+/// `1!0.4` is compared first.
+pub(crate) fn compare_conda_versions(a: &str, b: &str) -> Ordering {
+    // The epoch overrides everything else, and versions without one are in epoch 0
+    let ((a_epoch, a), (b_epoch, b)) = (split_epoch(a), split_epoch(b));
+    if a_epoch != b_epoch {
+        return a_epoch.cmp(&b_epoch);
+    }
+
+    let (a, b) = (components(a), components(b));
+
+    for i in 0..a.len().max(b.len()) {
+        let (a, b) = (
+            a.get(i).map(Vec::as_slice).unwrap_or_default(),
+            b.get(i).map(Vec::as_slice).unwrap_or_default(),
+        );
+        for j in 0..a.len().max(b.len()) {
+            match a.get(j).unwrap_or(&ZERO).cmp(b.get(j).unwrap_or(&ZERO)) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod conda_ordering {
+        use crate::version_order::*;
+
+        #[test]
+        fn padding() {
+            assert_eq!(compare_conda_versions("1", "1.0.0"), Ordering::Equal);
+            assert_eq!(compare_conda_versions("1.01", "1.1"), Ordering::Equal);
+            assert_eq!(compare_conda_versions("1.10", "1.9"), Ordering::Greater);
+            assert_eq!(compare_conda_versions("2022.10.1", "2022.9.30"), Ordering::Greater);
+            assert_eq!(compare_conda_versions("20230101000000000000", "2023"), Ordering::Greater);
+        }
+
+        #[test]
+        fn pep440_tags() {
+            assert_eq!(compare_conda_versions("1.0.dev1", "1.0"), Ordering::Less);
+            assert_eq!(compare_conda_versions("1.0.post1", "1.0"), Ordering::Greater);
+            assert_eq!(compare_conda_versions("1.0.post1", "1.0.1"), Ordering::Less);
+            assert_eq!(compare_conda_versions("1.0dev1", "1.0a1"), Ordering::Less);
+            assert_eq!(compare_conda_versions("1.0rc1", "1.0"), Ordering::Less);
+            assert_eq!(compare_conda_versions("1.0RC1", "1.0rc1"), Ordering::Equal);
+            assert_eq!(compare_conda_versions("4.3.post1", "4.3.post2"), Ordering::Less);
+        }
+
+        #[test]
+        fn epochs() {
+            assert_eq!(compare_conda_versions("1!0.1", "2022.1"), Ordering::Greater);
+            assert_eq!(compare_conda_versions("0!1.0", "1.0"), Ordering::Equal);
+            assert_eq!(compare_conda_versions("2!0.1", "1!5.0"), Ordering::Greater);
+            assert_eq!(compare_conda_versions("1!5.0", "1!1.0"), Ordering::Greater);
+        }
+
+        /// The ordering from conda's VersionOrder documentation
+        #[test]
+        fn conda_documented_order() {
+            let versions = [
+                "0.4", "0.4.1.rc", "0.4.1", "0.5a1", "0.5b3", "0.5C1", "0.5z", "0.5.a1", "0.5.0", "0.9.6",
+                "0.960923", "1.0", "1.1dev1", "1.1a1", "1.1.0dev1", "1.1.0a1", "1.1.0rc1", "1.1.0",
+                "1.1.0post1", "1.1post1", "1996.07.12", "1!0.4.1", "1!3.1.1.6", "2!0.4.1",
+            ];
+            for pair in versions.windows(2) {
+                assert_eq!(compare_conda_versions(pair[0], pair[1]), Ordering::Less, "{} < {}", pair[0], pair[1]);
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::matchspec::{compatible_upper_bound, prefix_upper_bound, strip_build_metadata, CompoundSelector, Selector};
+use crate::version_order::compare_conda_versions;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// Orders two versions, ignoring any `+build` metadata
+pub(crate) fn compare_versions(a: &str, b: &str) -> Ordering {
+    compare_conda_versions(strip_build_metadata(a), strip_build_metadata(b))
+}
+
+/// The bounds of a `1.11.*` series, from `1.11.dev` up to but not including `1.12dev`. `dev` sorts
+/// before every other tag, so this takes in `1.11.0a1` but leaves out `1.11a1` and `1.12a1`, the
+/// same as the prefix match in `Selector::eq`. None for anything that isn't a plain prefix glob.
+fn prefix_bounds(version: &str) -> Option<(String, String)> {
+    let prefix = version.strip_suffix(".*").filter(|prefix| !prefix.contains('*'))?;
+    Some((format!("{}.dev", prefix), format!("{}dev", prefix_upper_bound(prefix)?)))
+}
+
+/// One end of an interval. The version is only owned when it had to be worked out, like the upper
+/// bound of a `~=`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Bound<'a> {
+    pub version: Cow<'a, str>,
+    pub inclusive: bool,
+}
+
+/// A contiguous range of versions, `None` means unbounded on that side
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Interval<'a> {
+    pub lower: Option<Bound<'a>>,
+    pub upper: Option<Bound<'a>>,
+}
+
+impl<'a> Interval<'a> {
+    fn new(lower: Option<Bound<'a>>, upper: Option<Bound<'a>>) -> Self {
+        Interval { lower, upper }
+    }
+
+    /// Builds the intervals covered by a single selector and version
+    fn from_selector(selector: &Selector, version: &'a str) -> Vec<Interval<'a>> {
+        let bound = |inclusive| Some(Bound { version: Cow::Borrowed(version), inclusive });
+        if let (Selector::EqualTo, Some((lower, upper))) = (selector, prefix_bounds(version)) {
+            return vec![Interval::new(
+                Some(Bound { version: Cow::Owned(lower), inclusive: true }),
+                Some(Bound { version: Cow::Owned(upper), inclusive: false }),
+            )];
+        }
+        match selector {
+            Selector::EqualTo if version == "*" => vec![Interval::new(None, None)],
+            Selector::GreaterThan => vec![Interval::new(bound(false), None)],
+            Selector::GreaterThanOrEqualTo => vec![Interval::new(bound(true), None)],
+            Selector::LessThan => vec![Interval::new(None, bound(false))],
+            Selector::LessThanOrEqualTo => vec![Interval::new(None, bound(true))],
+            Selector::EqualTo | Selector::ExactlyEqualTo => {
+                vec![Interval::new(bound(true), bound(true))]
+            }
+            // `!=1.4.*` leaves out the same series `==1.4.*` covers, so pre-releases like 1.5a1 are
+            // still allowed
+            Selector::NotEqualTo if version.ends_with(".*") => match prefix_bounds(version) {
+                Some((lower, upper)) => vec![
+                    Interval::new(None, Some(Bound { version: Cow::Owned(lower), inclusive: false })),
+                    Interval::new(Some(Bound { version: Cow::Owned(upper), inclusive: true }), None),
+                ],
+                None => vec![Interval::new(None, None)],
+            },
+            Selector::NotEqualTo => vec![
+                Interval::new(None, bound(false)),
+                Interval::new(bound(false), None),
+            ],
+            Selector::CompatibleRelease => vec![Interval::new(
+                bound(true),
+                compatible_upper_bound(version).map(|upper| Bound { version: Cow::Owned(upper), inclusive: false }),
+            )],
+        }
+    }
+
+    /// True if no version can fall within this interval
+    pub fn is_empty(&self) -> bool {
+        match (&self.lower, &self.upper) {
+            (Some(lower), Some(upper)) => match compare_versions(&lower.version, &upper.version) {
+                Ordering::Greater => true,
+                Ordering::Equal => !(lower.inclusive && upper.inclusive),
+                Ordering::Less => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The interval covered by both self and other
+    pub fn intersect(&self, other: &Interval<'a>) -> Interval<'a> {
+        let pick = |a: &Option<Bound<'a>>, b: &Option<Bound<'a>>, keep: Ordering| match (a, b) {
+            (Some(a), Some(b)) => match compare_versions(&a.version, &b.version) {
+                Ordering::Equal => Some(Bound {
+                    version: a.version.clone(),
+                    inclusive: a.inclusive && b.inclusive,
+                }),
+                ordering if ordering == keep => Some(a.clone()),
+                _ => Some(b.clone()),
+            },
+            (Some(a), None) => Some(a.clone()),
+            (None, b) => b.clone(),
+        };
+
+        Interval {
+            lower: pick(&self.lower, &other.lower, Ordering::Greater),
+            upper: pick(&self.upper, &other.upper, Ordering::Less),
+        }
+    }
+}
+
+/// Every non-empty pairwise intersection of two sets of intervals
+fn intersect_all<'a>(a: &[Interval<'a>], b: &[Interval<'a>]) -> Vec<Interval<'a>> {
+    a.iter()
+        .flat_map(|x| b.iter().map(move |y| x.intersect(y)))
+        .filter(|i| !i.is_empty())
+        .collect()
+}
+
+/// Converts a CompoundSelector into the set of intervals that it matches
+pub(crate) fn intervals<S>(cs: &CompoundSelector<S>) -> Vec<Interval<'_>>
+where
+    S: Into<String> + AsRef<str>,
+{
+    match cs {
+        CompoundSelector::Single { selector, version } => {
+            Interval::from_selector(selector, version.as_ref())
+        }
+        CompoundSelector::And {
+            first_selector,
+            first_version,
+            second_selector,
+            second_version,
+        } => intersect_all(
+            &Interval::from_selector(first_selector, first_version.as_ref()),
+            &Interval::from_selector(second_selector, second_version.as_ref()),
+        ),
+        CompoundSelector::Or {
+            first_selector,
+            first_version,
+            second_selector,
+            second_version,
+        } => {
+            let mut intervals = Interval::from_selector(first_selector, first_version.as_ref());
+            intervals.extend(Interval::from_selector(second_selector, second_version.as_ref()));
+            intervals.retain(|i| !i.is_empty());
+            intervals
+        }
+    }
+}
+
+/// True if at least one version satisfies both CompoundSelectors
+pub(crate) fn overlaps<S, T>(a: &CompoundSelector<S>, b: &CompoundSelector<T>) -> bool
+where
+    S: Into<String> + AsRef<str>,
+    T: Into<String> + AsRef<str>,
+{
+    !intersect_all(&intervals(a), &intervals(b)).is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(test)]
+    mod intervals {
+        use crate::version_range::*;
+
+        #[test]
+        fn empty_intervals() {
+            let bound = |version, inclusive| Some(Bound { version: Cow::Borrowed(version), inclusive });
+
+            assert!(!Interval::new(None, None).is_empty());
+            assert!(!Interval::new(bound("1.0", true), bound("1.0", true)).is_empty());
+            assert!(Interval::new(bound("1.0", true), bound("1.0", false)).is_empty());
+            assert!(Interval::new(bound("2.0", true), bound("1.0", true)).is_empty());
+            assert!(!Interval::new(bound("1.0", false), bound("2.0", false)).is_empty());
+        }
+
+        #[test]
+        fn compound_selector_intervals() {
+            let cs: CompoundSelector<String> = ">=1.0,<2.0".parse().unwrap();
+            assert_eq!(
+                intervals(&cs),
+                vec![Interval::new(
+                    Some(Bound { version: "1.0".into(), inclusive: true }),
+                    Some(Bound { version: "2.0".into(), inclusive: false }),
+                )]
+            );
+
+            // Contradictory constraints have no intervals
+            let cs: CompoundSelector<String> = ">=2.0,<1.0".parse().unwrap();
+            assert!(intervals(&cs).is_empty());
+
+            let cs: CompoundSelector<String> = "<1.0|>2.0".parse().unwrap();
+            assert_eq!(intervals(&cs).len(), 2);
+
+            let cs: CompoundSelector<String> = "~=1.2.3".parse().unwrap();
+            assert_eq!(
+                intervals(&cs),
+                vec![Interval::new(
+                    Some(Bound { version: "1.2.3".into(), inclusive: true }),
+                    Some(Bound { version: "1.3".into(), inclusive: false }),
+                )]
+            );
+
+            // A bare glob is every version
+            let cs: CompoundSelector<String> = "=*".parse().unwrap();
+            assert_eq!(intervals(&cs), vec![Interval::new(None, None)]);
+
+            // A prefix glob is the whole series, without the pre-releases of the next one
+            let cs: CompoundSelector<String> = "=1.11.*".parse().unwrap();
+            assert_eq!(
+                intervals(&cs),
+                vec![Interval::new(
+                    Some(Bound { version: "1.11.dev".into(), inclusive: true }),
+                    Some(Bound { version: "1.12dev".into(), inclusive: false }),
+                )]
+            );
+            let series: CompoundSelector<String> = "=1.11.*".parse().unwrap();
+            for version in ["1.11", "1.11.0a1", "1.11.dev0", "1.11.9.post1"] {
+                assert!(overlaps(&series, &CompoundSelector::from(("=", version))), "{}", version);
+            }
+            for version in ["1.11a1", "1.12a1", "1.12.0rc1", "1.12.dev0", "1.12"] {
+                assert!(!overlaps(&series, &CompoundSelector::from(("=", version))), "{}", version);
+            }
+
+            let cs: CompoundSelector<String> = "!=1.4.*".parse().unwrap();
+            assert_eq!(
+                intervals(&cs),
+                vec![
+                    Interval::new(None, Some(Bound { version: "1.4.dev".into(), inclusive: false })),
+                    Interval::new(Some(Bound { version: "1.5dev".into(), inclusive: true }), None),
+                ]
+            );
+            for version in ["1.3.9", "1.5a1", "1.5.dev0", "1.5"] {
+                assert!(overlaps(&cs, &CompoundSelector::from(("=", version))), "{}", version);
+            }
+            for version in ["1.4", "1.4.0a1", "1.4.dev0", "1.4.9"] {
+                assert!(!overlaps(&cs, &CompoundSelector::from(("=", version))), "{}", version);
+            }
+        }
+    }
+}